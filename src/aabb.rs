@@ -0,0 +1,100 @@
+use crate::ray::Ray;
+use crate::vec::Vec3;
+
+/// 轴对齐包围盒 (Axis-Aligned Bounding Box)
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// An inverted box that contains nothing; the identity for `union`.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(a: Vec3, b: Vec3) -> Aabb {
+        Aabb {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(&self, p: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.0 > self.max.0 || self.min.1 > self.max.1 || self.min.2 > self.max.2
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.extent();
+        2.0 * (d.0 * d.1 + d.1 * d.2 + d.2 * d.0)
+    }
+
+    /// Index of the axis along which the box is largest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.0 > d.1 && d.0 > d.2 {
+            0
+        } else if d.1 > d.2 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test: the ray hits the box if the intervals where it lies
+    /// between each pair of planes overlap somewhere inside [t_min, t_max].
+    pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN (0 * inf) 不会缩小区间
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,224 @@
+use crate::distribution::Distribution2D;
+use crate::hdr::{self, HdrError, HdrImage};
+use crate::sky::PreethamSky;
+use crate::utils::degrees_to_radians;
+use crate::vec::Vec3;
+use std::f32::consts::PI;
+use std::path::Path;
+
+/// 光线没有击中任何物体时看到的颜色
+pub enum Background {
+    Solid(Vec3),
+    /// 按方向的 y 分量在两种颜色之间线性插值
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    Environment(EnvironmentMap),
+    /// 按太阳位置计算的解析天空，太阳需要用 `PreethamSky::sun` 另外加入光源列表
+    PhysicalSky(PreethamSky),
+}
+
+impl Default for Background {
+    fn default() -> Background {
+        Background::sky()
+    }
+}
+
+impl Background {
+    /// 书中从白色到天蓝色的渐变
+    pub fn sky() -> Background {
+        Background::Gradient {
+            bottom: Vec3(1.0, 1.0, 1.0),
+            top: Vec3(0.5, 0.7, 1.0),
+        }
+    }
+
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = direction.to_unit_vector();
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                (1.0 - t) * *bottom + t * *top
+            }
+            Background::Environment(map) => map.radiance(direction),
+            Background::PhysicalSky(sky) => sky.radiance(direction),
+        }
+    }
+}
+
+/// 经纬度 (equirectangular) 格式的 HDR 环境贴图
+/// 图像的 u 对应绕 y 轴的方位角，v 从上 (+y) 到下 (-y)
+pub struct EnvironmentMap {
+    image: HdrImage,
+    // 按亮度构建的采样分布，用于直接对环境光采样
+    distribution: Distribution2D,
+    // 绕 y 轴旋转的角度（弧度）
+    pub rotation: f32,
+    // 亮度倍数
+    pub intensity: f32,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage, rotation_degrees: f32, intensity: f32) -> EnvironmentMap {
+        // 每个像素对应的立体角正比于 sin(theta)，越靠近两极越小
+        let mut func = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            let sin_theta = libm::sinf(PI * (y as f32 + 0.5) / image.height as f32);
+            for x in 0..image.width {
+                func.push(image.get(x, y).luminance() * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&func, image.width, image.height);
+        EnvironmentMap {
+            image,
+            distribution,
+            rotation: degrees_to_radians(rotation_degrees),
+            intensity,
+        }
+    }
+
+    pub fn load(
+        path: &Path,
+        rotation_degrees: f32,
+        intensity: f32,
+    ) -> Result<EnvironmentMap, HdrError> {
+        Ok(EnvironmentMap::new(
+            hdr::load(path)?,
+            rotation_degrees,
+            intensity,
+        ))
+    }
+
+    pub fn image(&self) -> &HdrImage {
+        &self.image
+    }
+
+    /// 按亮度对方向重要性采样，返回 (方向, 辐射亮度, 立体角上的概率密度)
+    pub fn sample(&self, u0: f32, u1: f32) -> Option<(Vec3, Vec3, f32)> {
+        let ((u, v), uv_pdf) = self.distribution.sample_continuous(u0, u1);
+        let sin_theta = libm::sinf(v * PI);
+        if uv_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let direction = self.uv_to_direction(u, v);
+        // (u, v) 到立体角的雅可比行列式为 2π² sin(theta)
+        let pdf = uv_pdf / (2.0 * PI * PI * sin_theta);
+        Some((direction, self.radiance(direction), pdf))
+    }
+
+    /// `sample` 采到给定方向的概率密度
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = libm::sinf(v * PI);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// 方向到贴图坐标 (u, v)，都在 [0, 1] 内
+    pub fn direction_to_uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.to_unit_vector();
+        let theta = libm::acosf(d.y().clamp(-1.0, 1.0));
+        let phi = libm::atan2f(d.x(), -d.z()) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    /// 贴图坐标 (u, v) 到单位方向，`direction_to_uv` 的逆变换
+    pub fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let sin_theta = libm::sinf(theta);
+        Vec3(
+            sin_theta * libm::sinf(phi),
+            libm::cosf(theta),
+            -sin_theta * libm::cosf(phi),
+        )
+    }
+
+    /// 双线性插值采样，水平方向循环，竖直方向截断
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        let image = &self.image;
+        let x = u * image.width as f32 - 0.5;
+        let y = (v * image.height as f32 - 0.5).clamp(0.0, (image.height - 1) as f32);
+        let x0 = libm::floorf(x);
+        let y0 = libm::floorf(y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let wrap = |x: f32| (x as i64).rem_euclid(image.width as i64) as usize;
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(image.height - 1);
+        let top = (1.0 - fx) * image.get(x0, y0) + fx * image.get(x1, y0);
+        let bottom = (1.0 - fx) * image.get(x0, y1) + fx * image.get(x1, y1);
+        self.intensity * ((1.0 - fy) * top + fy * bottom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn estimate_irradiance(map: &EnvironmentMap, normal: Vec3, count: usize) -> f32 {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut sum = 0.0f64;
+        let mut mismatches = 0;
+        for _ in 0..count {
+            if let Some((direction, radiance, pdf)) = map.sample(rng.gen(), rng.gen()) {
+                // 像素边界和两极附近的舍入误差会让个别方向落到相邻像素
+                if (pdf - map.pdf(direction)).abs() > 1e-2 * pdf {
+                    mismatches += 1;
+                }
+                let cosine = direction.dot(normal).max(0.0);
+                sum += (radiance.0 * cosine / pdf) as f64;
+            }
+        }
+        assert!(mismatches < count / 1000, "{} pdf mismatches", mismatches);
+        (sum / count as f64) as f32
+    }
+
+    #[test]
+    fn uniform_environment_irradiance_is_pi() {
+        // 各处亮度为 1 的环境光在任意平面上的辐照度为 π
+        let image = HdrImage {
+            width: 64,
+            height: 32,
+            pixels: vec![Vec3(1.0, 1.0, 1.0); 64 * 32],
+        };
+        let map = EnvironmentMap::new(image, 30.0, 1.0);
+        for normal in [
+            Vec3(0.0, 1.0, 0.0),
+            Vec3(1.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, -1.0),
+        ] {
+            let estimate = estimate_irradiance(&map, normal, 200_000);
+            assert!((estimate - PI).abs() < 0.02, "estimate {}", estimate);
+        }
+    }
+
+    #[test]
+    fn upper_hemisphere_irradiance_is_two_pi() {
+        // 上半球亮度为 2、下半球为 0 时，朝上平面的辐照度为 2π，朝下为 0
+        let (width, height) = (64, 32);
+        let mut pixels = vec![Vec3(0.0, 0.0, 0.0); width * height];
+        for pixel in &mut pixels[..width * height / 2] {
+            *pixel = Vec3(2.0, 2.0, 2.0);
+        }
+        let image = HdrImage {
+            width,
+            height,
+            pixels,
+        };
+        let map = EnvironmentMap::new(image, 0.0, 1.0);
+        let up = estimate_irradiance(&map, Vec3(0.0, 1.0, 0.0), 200_000);
+        assert!((up - 2.0 * PI).abs() < 0.05, "estimate {}", up);
+        let down = estimate_irradiance(&map, Vec3(0.0, -1.0, 0.0), 200_000);
+        assert!(down.abs() < 1e-3, "estimate {}", down);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::aabb::Aabb;
+use crate::hit::{Hit, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::vec::Vec3;
+
+// 表面积启发式 (SAH) 划分时使用的桶数
+const BUCKET_COUNT: usize = 12;
+// 物体数不超过这个值时，才考虑直接做成叶子节点
+const MAX_OBJECTS_IN_LEAF: usize = 4;
+// 相对于求交一个物体的代价，遍历一个内部节点的代价
+const TRAVERSAL_COST: f32 = 0.125;
+
+struct BuildItem {
+    object: Box<dyn Hittable>,
+    bbox: Aabb,
+    centroid: Vec3,
+}
+
+enum BvhContent {
+    Leaf(HittableList),
+    Interior {
+        axis: usize,
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+    },
+}
+
+/// 层次包围盒 (Bounding Volume Hierarchy)
+/// 把物体按包围盒递归地分成两组，光线只需要和它穿过的包围盒里的物体求交。
+/// 划分位置由表面积启发式决定：代价正比于子节点包围盒的表面积乘以其中的物体数。
+pub struct BvhNode {
+    bbox: Aabb,
+    content: BvhContent,
+}
+
+impl BvhNode {
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> BvhNode {
+        let items = objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object.aabb();
+                BuildItem {
+                    object,
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+        BvhNode::build(items)
+    }
+
+    fn leaf(bbox: Aabb, items: Vec<BuildItem>) -> BvhNode {
+        BvhNode {
+            bbox,
+            content: BvhContent::Leaf(HittableList {
+                objects: items.into_iter().map(|item| item.object).collect(),
+            }),
+        }
+    }
+
+    fn interior(bbox: Aabb, axis: usize, left: Vec<BuildItem>, right: Vec<BuildItem>) -> BvhNode {
+        BvhNode {
+            bbox,
+            content: BvhContent::Interior {
+                axis,
+                left: Box::new(BvhNode::build(left)),
+                right: Box::new(BvhNode::build(right)),
+            },
+        }
+    }
+
+    fn build(mut items: Vec<BuildItem>) -> BvhNode {
+        let bbox = items
+            .iter()
+            .fold(Aabb::empty(), |acc, item| acc.union(&item.bbox));
+        let count = items.len();
+        if count <= 1 {
+            return BvhNode::leaf(bbox, items);
+        }
+
+        let centroid_bounds = items
+            .iter()
+            .fold(Aabb::empty(), |acc, item| acc.grow(item.centroid));
+        let axis = centroid_bounds.longest_axis();
+        let low = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - low;
+
+        // 所有中心点重合，没法按位置划分
+        if extent <= 0.0 {
+            if count <= MAX_OBJECTS_IN_LEAF {
+                return BvhNode::leaf(bbox, items);
+            }
+            let right = items.split_off(count / 2);
+            return BvhNode::interior(bbox, axis, items, right);
+        }
+
+        let bucket_of = |centroid: Vec3| -> usize {
+            let b = (BUCKET_COUNT as f32 * (centroid[axis] - low) / extent) as usize;
+            b.min(BUCKET_COUNT - 1)
+        };
+
+        let mut counts = [0usize; BUCKET_COUNT];
+        let mut bounds = [Aabb::empty(); BUCKET_COUNT];
+        for item in &items {
+            let b = bucket_of(item.centroid);
+            counts[b] += 1;
+            bounds[b] = bounds[b].union(&item.bbox);
+        }
+
+        // 从右往左扫一遍，得到每个划分位置右侧的包围盒和数量
+        let mut right_area = [0.0f32; BUCKET_COUNT];
+        let mut right_count = [0usize; BUCKET_COUNT];
+        let mut acc_bounds = Aabb::empty();
+        let mut acc_count = 0;
+        for i in (1..BUCKET_COUNT).rev() {
+            acc_bounds = acc_bounds.union(&bounds[i]);
+            acc_count += counts[i];
+            right_area[i] = acc_bounds.surface_area();
+            right_count[i] = acc_count;
+        }
+
+        // 再从左往右扫，计算在第 i 个桶之后划分的代价
+        let parent_area = bbox.surface_area();
+        let inv_area = if parent_area > 0.0 {
+            1.0 / parent_area
+        } else {
+            0.0
+        };
+        let mut best_split = 0;
+        let mut best_cost = f32::INFINITY;
+        let mut acc_bounds = Aabb::empty();
+        let mut acc_count = 0;
+        for split in 0..BUCKET_COUNT - 1 {
+            acc_bounds = acc_bounds.union(&bounds[split]);
+            acc_count += counts[split];
+            if acc_count == 0 || right_count[split + 1] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (acc_bounds.surface_area() * acc_count as f32
+                    + right_area[split + 1] * right_count[split + 1] as f32)
+                    * inv_area;
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+
+        let leaf_cost = count as f32;
+        if count <= MAX_OBJECTS_IN_LEAF && leaf_cost <= best_cost {
+            return BvhNode::leaf(bbox, items);
+        }
+
+        let (left, right): (Vec<BuildItem>, Vec<BuildItem>) = items
+            .into_iter()
+            .partition(|item| bucket_of(item.centroid) <= best_split);
+        BvhNode::interior(bbox, axis, left, right)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+        match &self.content {
+            BvhContent::Leaf(list) => list.hit(t_min, t_max, ray),
+            BvhContent::Interior { axis, left, right } => {
+                // 先访问离光线起点更近的子节点，这样后一个节点的 t_max 会更小
+                let (near, far) = if ray.direction[*axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                let near_hit = near.hit(t_min, t_max, ray);
+                let closest_so_far = near_hit.as_ref().map_or(t_max, |rec| rec.t);
+                far.hit(t_min, closest_so_far, ray).or(near_hit)
+            }
+        }
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+
+    // 沿途所有物体的透射率相乘，不能只看最近的交点
+    fn transmittance(&self, t_min: f32, t_max: f32, ray: &Ray) -> f32 {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return 1.0;
+        }
+        match &self.content {
+            BvhContent::Leaf(list) => list.transmittance(t_min, t_max, ray),
+            BvhContent::Interior { left, right, .. } => {
+                let left = left.transmittance(t_min, t_max, ray);
+                if left <= 0.0 {
+                    return 0.0;
+                }
+                left * right.transmittance(t_min, t_max, ray)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::Sphere;
+    use crate::materials::Lambertian;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+
+    fn random_spheres(rng: &mut StdRng, count: usize) -> Vec<Box<dyn Hittable>> {
+        let material = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        (0..count)
+            .map(|_| {
+                let center = Vec3(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                Box::new(Sphere {
+                    center,
+                    radius: rng.gen_range(0.05..1.5),
+                    material: material.clone(),
+                }) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    fn assert_same_hits(list: &HittableList, bvh: &BvhNode, rng: &mut StdRng) {
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let origin = Vec3(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let target = Vec3(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let ray = Ray::new(origin, target - origin);
+            let expected = list.hit(0.001, f32::MAX, &ray);
+            let actual = bvh.hit(0.001, f32::MAX, &ray);
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(e), Some(a)) => {
+                    hits += 1;
+                    assert_eq!(e.t, a.t);
+                    assert!((e.p - a.p).length() < 1e-5);
+                }
+                (e, a) => panic!(
+                    "list hit: {:?}, bvh hit: {:?}",
+                    e.map(|h| h.t),
+                    a.map(|h| h.t)
+                ),
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn bvh_matches_linear_list() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let list = HittableList {
+            objects: random_spheres(&mut StdRng::seed_from_u64(42), 500),
+        };
+        let bvh = BvhNode::new(random_spheres(&mut StdRng::seed_from_u64(42), 500));
+        assert_same_hits(&list, &bvh, &mut rng);
+    }
+
+    #[test]
+    fn bvh_handles_coincident_centroids() {
+        let material = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let spheres = |material: Arc<Lambertian>| -> Vec<Box<dyn Hittable>> {
+            (1..20)
+                .map(|i| {
+                    Box::new(Sphere {
+                        center: Vec3(0.0, 0.0, 0.0),
+                        radius: i as f32 * 0.5,
+                        material: material.clone(),
+                    }) as Box<dyn Hittable>
+                })
+                .collect()
+        };
+        let list = HittableList {
+            objects: spheres(material.clone()),
+        };
+        let bvh = BvhNode::new(spheres(material));
+        assert_same_hits(&list, &bvh, &mut StdRng::seed_from_u64(3));
+    }
+}
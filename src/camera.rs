@@ -1,5 +1,5 @@
 use crate::ray::Ray;
-use crate::utils::degrees_to_radians;
+use crate::utils::{degrees_to_radians, random_range};
 use crate::vec::Vec3;
 
 #[derive(Clone, Copy, Debug)]
@@ -10,8 +10,10 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f32,
+    // 快门打开和关闭的时刻，光线的时间在两者之间均匀分布
+    time0: f32,
+    time1: f32,
 }
 
 impl Camera {
@@ -44,18 +46,31 @@ impl Camera {
             vertical,
             u,
             v,
-            w,
             lower_left_corner,
             lens_radius,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
 
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.time0 = open;
+        self.time1 = close;
+        self
+    }
+
     pub fn get_ray(&self, s: f32, t: f32) -> Ray {
         let rd = self.lens_radius * Vec3::random_in_unit_disk();
         let offset = rd.x() * self.u + rd.y() * self.v;
-        Ray::new(
+        let time = if self.time1 > self.time0 {
+            random_range(self.time0, self.time1)
+        } else {
+            self.time0
+        };
+        Ray::with_time(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time,
         )
     }
 }
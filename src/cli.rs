@@ -0,0 +1,428 @@
+use clap::Parser;
+use raytrace::background::{Background, EnvironmentMap};
+use raytrace::hdr::HdrError;
+use raytrace::output::{ExrPrecision, ImageFormat, OutputSettings};
+use raytrace::scene::CameraSettings;
+use raytrace::tonemap::{ToneMapOperator, ToneMapping};
+use raytrace::utils::degrees_to_radians;
+use raytrace::{RenderSettings, Vec3};
+use std::path::PathBuf;
+
+/// 要渲染的场景：内置场景或者模型文件
+#[derive(Clone, Debug)]
+pub enum SceneSource {
+    Random,
+    Obj(PathBuf),
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub enum BackgroundSource {
+    Sky,
+    Solid(Vec3),
+    Environment(PathBuf),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ToneMapKind {
+    None,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    Uncharted2,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "A ray tracer following Ray Tracing in One Weekend")]
+pub struct Args {
+    /// Scene to render: `random`, an .obj model or a .toml scene file
+    #[arg(long, default_value = "random", value_parser = parse_scene)]
+    pub scene: SceneSource,
+
+    /// Output image path (.png, .exr or .pfm); repeat to write several formats
+    #[arg(short, long, default_value = "image.png", value_parser = parse_output)]
+    pub output: Vec<PathBuf>,
+
+    /// Channel precision for OpenEXR output: `half` or `float`
+    #[arg(long, default_value = "float", value_parser = parse_exr_precision)]
+    pub exr_precision: ExrPrecision,
+
+    /// Tone mapping for PNG output: `none`, `reinhard`, `extended-reinhard`, `aces` or `uncharted2`
+    #[arg(long, default_value = "none", value_parser = parse_tone_map)]
+    pub tone_map: ToneMapKind,
+
+    /// White point for `extended-reinhard`; defaults to the brightest pixel
+    #[arg(long, value_parser = parse_positive)]
+    pub white_point: Option<f32>,
+
+    /// Exposure compensation for PNG output, in EV stops
+    #[arg(long, default_value_t = 0.0, value_parser = parse_float, allow_hyphen_values = true)]
+    pub exposure: f32,
+
+    /// Image width in pixels [default: 1200]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..))]
+    pub width: Option<u32>,
+
+    /// Aspect ratio, either as `W:H` or as a number [default: 3:2]
+    #[arg(long, value_parser = parse_aspect_ratio)]
+    pub aspect_ratio: Option<f32>,
+
+    /// Samples per pixel [default: 500]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Maximum number of ray bounces [default: 50]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_depth: Option<u32>,
+
+    /// Bounces before Russian roulette may terminate a path; at most `--max-depth` [default: 3]
+    #[arg(long)]
+    pub rr_depth: Option<u32>,
+
+    /// Camera position as `x,y,z` [default: 13,2,3]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub look_from: Option<Vec3>,
+
+    /// Point the camera looks at as `x,y,z` [default: 0,0,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub look_at: Option<Vec3>,
+
+    /// Camera up vector as `x,y,z` [default: 0,1,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees [default: 20]
+    #[arg(long, value_parser = parse_fov)]
+    pub vfov: Option<f32>,
+
+    /// Lens aperture; 0 disables depth of field [default: 0.1]
+    #[arg(long, value_parser = parse_non_negative)]
+    pub aperture: Option<f32>,
+
+    /// Distance to the plane in perfect focus [default: 10]
+    #[arg(long, value_parser = parse_positive)]
+    pub focus_dist: Option<f32>,
+
+    /// Time at which the shutter opens [default: 0]
+    #[arg(long, value_parser = parse_float, allow_hyphen_values = true)]
+    pub shutter_open: Option<f32>,
+
+    /// Time at which the shutter closes; later than the opening time enables motion blur [default: 0]
+    #[arg(long, value_parser = parse_float, allow_hyphen_values = true)]
+    pub shutter_close: Option<f32>,
+
+    /// Background: `sky`, a solid color as `r,g,b`, or an .hdr/.pfm environment map
+    #[arg(long, value_parser = parse_background, allow_hyphen_values = true)]
+    pub background: Option<BackgroundSource>,
+
+    /// Rotation of the environment map around the up axis, in degrees
+    #[arg(long, value_parser = parse_float, allow_hyphen_values = true)]
+    pub env_rotation: Option<f32>,
+
+    /// Brightness multiplier for the environment map
+    #[arg(long, value_parser = parse_non_negative)]
+    pub env_intensity: Option<f32>,
+
+    /// Number of render threads; 0 uses all cores
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+
+    /// Random seed; a random one is picked and printed when omitted
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl Args {
+    /// 命令行中给出的参数覆盖场景文件里的设置
+    pub fn apply(
+        &self,
+        render: &mut RenderSettings,
+        camera: &mut CameraSettings,
+    ) -> Result<(), String> {
+        if let Some(width) = self.width {
+            render.width = width as usize;
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            render.aspect_ratio = aspect_ratio;
+        }
+        if let Some(samples) = self.samples {
+            render.samples_per_pixel = samples as usize;
+        }
+        if let Some(max_depth) = self.max_depth {
+            render.max_depth = max_depth as usize;
+            render.rr_depth = render.rr_depth.min(render.max_depth);
+        }
+        if let Some(rr_depth) = self.rr_depth {
+            render.rr_depth = rr_depth as usize;
+        }
+        camera.look_from = self.look_from.unwrap_or(camera.look_from);
+        camera.look_at = self.look_at.unwrap_or(camera.look_at);
+        camera.vup = self.vup.unwrap_or(camera.vup);
+        camera.vfov = self.vfov.unwrap_or(camera.vfov);
+        camera.aperture = self.aperture.unwrap_or(camera.aperture);
+        camera.focus_dist = self.focus_dist.unwrap_or(camera.focus_dist);
+        camera.shutter_open = self.shutter_open.unwrap_or(camera.shutter_open);
+        camera.shutter_close = self.shutter_close.unwrap_or(camera.shutter_close);
+        render.validate()?;
+        camera.validate()
+    }
+
+    pub fn output_settings(&self) -> OutputSettings {
+        let operator = match self.tone_map {
+            ToneMapKind::None => ToneMapOperator::None,
+            ToneMapKind::Reinhard => ToneMapOperator::Reinhard,
+            ToneMapKind::ExtendedReinhard => ToneMapOperator::ExtendedReinhard {
+                white_point: self.white_point,
+            },
+            ToneMapKind::Aces => ToneMapOperator::Aces,
+            ToneMapKind::Uncharted2 => ToneMapOperator::Uncharted2,
+        };
+        OutputSettings {
+            exr_precision: self.exr_precision,
+            tone_mapping: ToneMapping {
+                operator,
+                exposure: self.exposure,
+            },
+        }
+    }
+
+    /// 替换背景，并调整环境贴图的旋转和亮度
+    pub fn apply_background(&self, background: &mut Background) -> Result<(), HdrError> {
+        match &self.background {
+            Some(BackgroundSource::Sky) => *background = Background::sky(),
+            Some(BackgroundSource::Solid(color)) => *background = Background::Solid(*color),
+            Some(BackgroundSource::Environment(path)) => {
+                *background = Background::Environment(EnvironmentMap::load(path, 0.0, 1.0)?)
+            }
+            None => {}
+        }
+        if let Background::Environment(map) = background {
+            if let Some(rotation) = self.env_rotation {
+                map.rotation = degrees_to_radians(rotation);
+            }
+            if let Some(intensity) = self.env_intensity {
+                map.intensity = intensity;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_background(s: &str) -> Result<BackgroundSource, String> {
+    if s == "sky" {
+        return Ok(BackgroundSource::Sky);
+    }
+    if let Ok(color) = parse_vec3(s) {
+        return Ok(BackgroundSource::Solid(color));
+    }
+    let path = PathBuf::from(s);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("pfm") => {
+            Ok(BackgroundSource::Environment(path))
+        }
+        _ => {
+            Err("expected `sky`, a color as `r,g,b`, or a path to an .hdr or .pfm file".to_string())
+        }
+    }
+}
+
+fn parse_output(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    match ImageFormat::from_path(&path) {
+        Some(_) => Ok(path),
+        None => Err("expected a path ending in .png, .exr or .pfm".to_string()),
+    }
+}
+
+fn parse_exr_precision(s: &str) -> Result<ExrPrecision, String> {
+    match s {
+        "half" => Ok(ExrPrecision::Half),
+        "float" => Ok(ExrPrecision::Float),
+        _ => Err("expected `half` or `float`".to_string()),
+    }
+}
+
+fn parse_tone_map(s: &str) -> Result<ToneMapKind, String> {
+    match s {
+        "none" => Ok(ToneMapKind::None),
+        "reinhard" => Ok(ToneMapKind::Reinhard),
+        "extended-reinhard" => Ok(ToneMapKind::ExtendedReinhard),
+        "aces" => Ok(ToneMapKind::Aces),
+        "uncharted2" => Ok(ToneMapKind::Uncharted2),
+        _ => Err(
+            "expected `none`, `reinhard`, `extended-reinhard`, `aces` or `uncharted2`".to_string(),
+        ),
+    }
+}
+
+fn parse_scene(s: &str) -> Result<SceneSource, String> {
+    if s == "random" {
+        return Ok(SceneSource::Random);
+    }
+    let path = PathBuf::from(s);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("obj") => Ok(SceneSource::Obj(path)),
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => Ok(SceneSource::File(path)),
+        _ => Err("expected `random` or a path to an .obj or .toml file".to_string()),
+    }
+}
+
+fn parse_float(s: &str) -> Result<f32, String> {
+    let value: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", s))?;
+    if !value.is_finite() {
+        return Err(format!("`{}` is not a finite number", s));
+    }
+    Ok(value)
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    let value = parse_float(s)?;
+    if value <= 0.0 {
+        return Err("must be greater than 0".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    let value = parse_float(s)?;
+    if value < 0.0 {
+        return Err("must not be negative".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_fov(s: &str) -> Result<f32, String> {
+    let value = parse_float(s)?;
+    if value <= 0.0 || value >= 180.0 {
+        return Err("must be between 0 and 180 degrees (exclusive)".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+    match s.split_once(':') {
+        Some((w, h)) => Ok(parse_positive(w)? / parse_positive(h)?),
+        None => parse_positive(s),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    if parts.len() != 3 {
+        return Err(format!(
+            "expected three comma-separated numbers, got `{}`",
+            s
+        ));
+    }
+    Ok(Vec3(
+        parse_float(parts[0])?,
+        parse_float(parts[1])?,
+        parse_float(parts[2])?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::try_parse_from(std::iter::once("raytrace").chain(args.iter().copied()))
+            .map_err(|e| e.to_string())
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).unwrap_err()
+    }
+
+    #[test]
+    fn aspect_ratios() {
+        let args = parse(&["--aspect-ratio", "16:9"]).unwrap();
+        assert!((args.aspect_ratio.unwrap() - 16.0 / 9.0).abs() < 1e-6);
+        let args = parse(&["--aspect-ratio", "1.5"]).unwrap();
+        assert_eq!(args.aspect_ratio, Some(1.5));
+
+        assert!(error(&["--aspect-ratio", "16:0"]).contains("must be greater than 0"));
+        assert!(error(&["--aspect-ratio=-2"]).contains("must be greater than 0"));
+        assert!(error(&["--aspect-ratio", "wide"]).contains("`wide` is not a number"));
+        assert!(error(&["--aspect-ratio", "inf"]).contains("`inf` is not a finite number"));
+    }
+
+    #[test]
+    fn vectors() {
+        let args = parse(&["--look-from", "-1, 2.5,3", "--vup", "0,0,1"]).unwrap();
+        let look_from = args.look_from.unwrap();
+        assert_eq!((look_from.0, look_from.1, look_from.2), (-1.0, 2.5, 3.0));
+        let vup = args.vup.unwrap();
+        assert_eq!((vup.0, vup.1, vup.2), (0.0, 0.0, 1.0));
+
+        assert!(error(&["--look-at", "1,2"])
+            .contains("expected three comma-separated numbers, got `1,2`"));
+        assert!(error(&["--look-at", "1,2,3,4"]).contains("expected three comma-separated"));
+        assert!(error(&["--look-at", "1,x,3"]).contains("`x` is not a number"));
+        assert!(error(&["--look-at", "1,NaN,3"]).contains("`NaN` is not a finite number"));
+    }
+
+    #[test]
+    fn field_of_view() {
+        assert_eq!(parse(&["--vfov", "90"]).unwrap().vfov, Some(90.0));
+        for vfov in ["0", "180", "-10", "270"] {
+            assert!(
+                error(&[&format!("--vfov={}", vfov)])
+                    .contains("must be between 0 and 180 degrees (exclusive)"),
+                "{}",
+                vfov
+            );
+        }
+    }
+
+    #[test]
+    fn backgrounds() {
+        let args = parse(&["--background", "sky"]).unwrap();
+        assert!(matches!(args.background, Some(BackgroundSource::Sky)));
+        let args = parse(&["--background", "0.1,0.2,0.3"]).unwrap();
+        match args.background {
+            Some(BackgroundSource::Solid(color)) => {
+                assert_eq!((color.0, color.1, color.2), (0.1, 0.2, 0.3))
+            }
+            other => panic!("{:?}", other),
+        }
+        for path in ["studio.hdr", "studio.HDR", "probe.pfm"] {
+            let args = parse(&["--background", path]).unwrap();
+            match args.background {
+                Some(BackgroundSource::Environment(p)) => assert_eq!(p, PathBuf::from(path)),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        let expected = "expected `sky`, a color as `r,g,b`, or a path to an .hdr or .pfm file";
+        assert!(error(&["--background", "studio.png"]).contains(expected));
+        assert!(error(&["--background", "1,2"]).contains(expected));
+    }
+
+    #[test]
+    fn rr_depth_must_not_exceed_max_depth() {
+        let apply = |args: &[&str]| {
+            let mut render = RenderSettings::default();
+            let mut camera = CameraSettings::default();
+            parse(args)
+                .unwrap()
+                .apply(&mut render, &mut camera)
+                .map(|()| (render.max_depth, render.rr_depth))
+        };
+        assert_eq!(apply(&["--max-depth", "8", "--rr-depth", "8"]), Ok((8, 8)));
+        // 只降低 max_depth 时默认的 rr_depth 跟着降低
+        assert_eq!(apply(&["--max-depth", "2"]), Ok((2, 2)));
+        assert_eq!(
+            apply(&["--max-depth", "4", "--rr-depth", "5"]),
+            Err("rr_depth 5 must not be greater than max_depth 4".to_string())
+        );
+        // 场景里的 max_depth 默认是 50
+        assert_eq!(
+            apply(&["--rr-depth", "60"]),
+            Err("rr_depth 60 must not be greater than max_depth 50".to_string())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+/// 一维分段常数分布
+/// 按照给定的函数值在 [0, 1) 上按比例采样
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    func_int: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        assert!(!func.is_empty());
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].max(0.0) / n as f32;
+        }
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            // 函数处处为零时退化为均匀分布
+            *c = if func_int > 0.0 {
+                *c / func_int
+            } else {
+                i as f32 / n as f32
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// 函数在 [0, 1) 上的积分
+    pub fn integral(&self) -> f32 {
+        self.func_int
+    }
+
+    /// 把 [0, 1) 上的均匀随机数变换成按函数值分布的 x，返回 (x, 概率密度, 所在的段)
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.count();
+        // 找到最后一个 cdf[offset] <= u 的位置
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = self.pdf_at(offset);
+        let x = ((offset as f32 + du) / n as f32).min(1.0 - f32::EPSILON);
+        (x, pdf, offset)
+    }
+
+    fn pdf_at(&self, offset: usize) -> f32 {
+        if self.func_int > 0.0 {
+            self.func[offset].max(0.0) / self.func_int
+        } else {
+            1.0
+        }
+    }
+}
+
+/// 二维分段常数分布，先按边缘分布选 v，再按条件分布选 u
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` 按行存放，每行 `nu` 个值，共 `nv` 行
+    pub fn new(func: &[f32], nu: usize, nv: usize) -> Distribution2D {
+        assert_eq!(func.len(), nu * nv);
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(nu)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// 返回 ((u, v), 概率密度)，密度相对于 [0, 1)^2 上的面积
+    pub fn sample_continuous(&self, u0: f32, u1: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((u * nu as f32) as usize).min(nu - 1);
+        let iv = ((v * nv as f32) as usize).min(nv - 1);
+        self.conditional[iv].pdf_at(iu) * self.marginal.pdf_at(iv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn importance_sampled_estimate_converges_to_analytic_integral() {
+        // ∫∫ (u² + sin(πv)) du dv = 1/3 + 2/π
+        let f = |u: f32, v: f32| u * u + (PI * v).sin();
+        let expected = 1.0 / 3.0 + 2.0 / PI;
+
+        let (nu, nv) = (32, 16);
+        let mut func = vec![];
+        for y in 0..nv {
+            for x in 0..nu {
+                func.push(f(
+                    (x as f32 + 0.5) / nu as f32,
+                    (y as f32 + 0.5) / nv as f32,
+                ));
+            }
+        }
+        let distribution = Distribution2D::new(&func, nu, nv);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let count = 200_000;
+        let mut sum = 0.0f64;
+        for _ in 0..count {
+            let ((u, v), pdf) = distribution.sample_continuous(rng.gen(), rng.gen());
+            assert!(pdf > 0.0);
+            assert!((pdf - distribution.pdf(u, v)).abs() < 1e-3 * pdf);
+            sum += (f(u, v) / pdf) as f64;
+        }
+        let estimate = (sum / count as f64) as f32;
+        assert!(
+            (estimate - expected).abs() < 2e-3,
+            "estimate {} expected {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn sampling_follows_the_tabulated_function() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert!((distribution.integral() - 4.0 / 3.0).abs() < 1e-6);
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut counts = [0usize; 3];
+        for _ in 0..100_000 {
+            let (x, pdf, offset) = distribution.sample_continuous(rng.gen());
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(offset, (x * 3.0) as usize);
+            assert!(pdf > 0.0);
+            counts[offset] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        let ratio = counts[2] as f32 / counts[0] as f32;
+        assert!((ratio - 3.0).abs() < 0.1, "ratio {}", ratio);
+    }
+}
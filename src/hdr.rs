@@ -0,0 +1,463 @@
+use crate::vec::Vec3;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// 浮点 RGB 图像，按行从上到下存放
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl HdrImage {
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+#[derive(Debug)]
+pub enum HdrError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Format {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdrError::Io { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            HdrError::Format { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for HdrError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HdrError::Io { source, .. } => Some(source),
+            HdrError::Format { .. } => None,
+        }
+    }
+}
+
+/// 根据扩展名读取 Radiance (.hdr) 或 PFM (.pfm) 图像
+pub fn load(path: &Path) -> Result<HdrImage, HdrError> {
+    let format_error = |message: String| HdrError::Format {
+        path: path.to_path_buf(),
+        message,
+    };
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let parse = match extension.as_deref() {
+        Some("hdr") | Some("pic") => parse_radiance,
+        Some("pfm") => parse_pfm,
+        _ => {
+            return Err(format_error(
+                "unsupported image format, expected .hdr or .pfm".to_string(),
+            ))
+        }
+    };
+    let data = std::fs::read(path).map_err(|source| HdrError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(&data).map_err(format_error)
+}
+
+/// 按字节读取文件内容
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&[u8], String> {
+        if self.pos + count > self.data.len() {
+            return Err("unexpected end of file".to_string());
+        }
+        let slice = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn line(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.byte()? != b'\n' {}
+        Ok(String::from_utf8_lossy(&self.data[start..self.pos - 1]).into_owned())
+    }
+
+    /// 跳过空白读取一个记号，并吃掉紧随其后的一个空白字符
+    fn token(&mut self) -> Result<String, String> {
+        while self.byte()?.is_ascii_whitespace() {}
+        let start = self.pos - 1;
+        while !self.byte()?.is_ascii_whitespace() {}
+        Ok(String::from_utf8_lossy(&self.data[start..self.pos - 1]).into_owned())
+    }
+}
+
+fn rgbe_to_vec3(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let scale = libm::ldexpf(1.0, rgbe[3] as i32 - (128 + 8));
+    Vec3(
+        rgbe[0] as f32 * scale,
+        rgbe[1] as f32 * scale,
+        rgbe[2] as f32 * scale,
+    )
+}
+
+/// 读取一行扫描线，支持新式 RLE、旧式 RLE 和未压缩三种编码
+fn read_scanline(reader: &mut Reader, width: usize) -> Result<Vec<[u8; 4]>, String> {
+    let mut line = vec![[0u8; 4]; width];
+    let head = reader.bytes(4)?;
+    let head = [head[0], head[1], head[2], head[3]];
+    if (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0 {
+        let encoded_width = ((head[2] as usize) << 8) | head[3] as usize;
+        if encoded_width != width {
+            return Err(format!(
+                "scanline width {} does not match image width {}",
+                encoded_width, width
+            ));
+        }
+        // 新式 RLE：四个通道分别编码
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = reader.byte()? as usize;
+                if count > 128 {
+                    let run = count - 128;
+                    if x + run > width {
+                        return Err("run-length data overflows scanline".to_string());
+                    }
+                    let value = reader.byte()?;
+                    for pixel in &mut line[x..x + run] {
+                        pixel[channel] = value;
+                    }
+                    x += run;
+                } else {
+                    if count == 0 || x + count > width {
+                        return Err("invalid run-length data".to_string());
+                    }
+                    for pixel in &mut line[x..x + count] {
+                        pixel[channel] = reader.byte()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+        return Ok(line);
+    }
+
+    let mut x = 0;
+    let mut shift = 0;
+    let mut rgbe = head;
+    loop {
+        if rgbe[0] == 1 && rgbe[1] == 1 && rgbe[2] == 1 {
+            // 旧式 RLE：重复上一个像素
+            if x == 0 {
+                return Err("run-length repeat at start of scanline".to_string());
+            }
+            let run = (rgbe[3] as usize) << shift;
+            if x + run > width {
+                return Err("run-length data overflows scanline".to_string());
+            }
+            let previous = line[x - 1];
+            for pixel in &mut line[x..x + run] {
+                *pixel = previous;
+            }
+            x += run;
+            shift += 8;
+        } else {
+            line[x] = rgbe;
+            x += 1;
+            shift = 0;
+        }
+        if x >= width {
+            return Ok(line);
+        }
+        let next = reader.bytes(4)?;
+        rgbe = [next[0], next[1], next[2], next[3]];
+    }
+}
+
+fn parse_radiance(data: &[u8]) -> Result<HdrImage, String> {
+    let mut reader = Reader { data, pos: 0 };
+    let magic = reader.line()?;
+    if !magic.starts_with("#?") {
+        return Err("missing Radiance `#?` header".to_string());
+    }
+    loop {
+        let line = reader.line()?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported pixel format `{}`", format.trim()));
+            }
+        }
+    }
+
+    let resolution = reader.line()?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match parts.as_slice() {
+        ["-Y", h, "+X", w] => (false, h, w),
+        ["+Y", h, "+X", w] => (true, h, w),
+        _ => {
+            return Err(format!(
+                "unsupported resolution line `{}`, expected `-Y <height> +X <width>`",
+                resolution.trim()
+            ))
+        }
+    };
+    let parse_size = |s: &str| -> Result<usize, String> {
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("invalid image size `{}`", s)),
+        }
+    };
+    let width = parse_size(width)?;
+    let height = parse_size(height)?;
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for _ in 0..height {
+        let line = read_scanline(&mut reader, width)?;
+        pixels.extend(line.into_iter().map(rgbe_to_vec3));
+    }
+    let mut image = HdrImage {
+        width,
+        height,
+        pixels,
+    };
+    if flip {
+        flip_rows(&mut image);
+    }
+    Ok(image)
+}
+
+fn flip_rows(image: &mut HdrImage) {
+    let width = image.width;
+    for y in 0..image.height / 2 {
+        let other = image.height - 1 - y;
+        for x in 0..width {
+            image.pixels.swap(y * width + x, other * width + x);
+        }
+    }
+}
+
+fn parse_pfm(data: &[u8]) -> Result<HdrImage, String> {
+    let mut reader = Reader { data, pos: 0 };
+    let channels = match reader.token()?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(format!("unknown PFM type `{}`", magic)),
+    };
+    let parse_size = |s: String| -> Result<usize, String> {
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("invalid image size `{}`", s)),
+        }
+    };
+    let width = parse_size(reader.token()?)?;
+    let height = parse_size(reader.token()?)?;
+    let scale_token = reader.token()?;
+    let scale: f32 = scale_token
+        .parse()
+        .map_err(|_| format!("invalid scale `{}`", scale_token))?;
+    if scale == 0.0 {
+        return Err("scale must not be zero".to_string());
+    }
+    // 比例因子为负表示小端序
+    let little_endian = scale < 0.0;
+
+    let raw = reader.bytes(width * height * channels * 4)?;
+    let floats: Vec<f32> = raw
+        .chunks_exact(4)
+        .map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            }
+        })
+        .collect();
+    let pixels = floats
+        .chunks_exact(channels)
+        .map(|c| {
+            if channels == 3 {
+                Vec3(c[0], c[1], c[2])
+            } else {
+                Vec3(c[0], c[0], c[0])
+            }
+        })
+        .collect();
+    // PFM 的行是从下到上存放的
+    let mut image = HdrImage {
+        width,
+        height,
+        pixels,
+    };
+    flip_rows(&mut image);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radiance(resolution: &str, pixels: &[u8]) -> Vec<u8> {
+        let mut data =
+            format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    fn assert_pixel(image: &HdrImage, x: usize, y: usize, expected: Vec3) {
+        let pixel = image.get(x, y);
+        assert!(
+            (pixel - expected).length() < 1e-6,
+            "({}, {}): {:?} != {:?}",
+            x,
+            y,
+            pixel,
+            expected
+        );
+    }
+
+    #[test]
+    fn new_style_run_length_encoding() {
+        // 每个通道分开编码：大于 128 的计数是重复，其余是原样的字节
+        let mut pixels = vec![2, 2, 0, 8];
+        pixels.extend([136, 128]);
+        pixels.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        pixels.extend([132, 0, 4, 1, 2, 3, 4]);
+        pixels.extend([136, 136]);
+        let image = parse_radiance(&radiance("-Y 1 +X 8", &pixels)).unwrap();
+        assert_eq!((image.width, image.height), (8, 1));
+        // 指数 136 的缩放是 1
+        for x in 0..8usize {
+            let blue = x.saturating_sub(3) as f32;
+            assert_pixel(&image, x, 0, Vec3(128.0, 16.0 * x as f32, blue));
+        }
+
+        // 编码的宽度必须和图像宽度一致
+        let mut wrong = pixels.clone();
+        wrong[3] = 9;
+        assert_eq!(
+            parse_radiance(&radiance("-Y 1 +X 8", &wrong)).unwrap_err(),
+            "scanline width 9 does not match image width 8"
+        );
+        // 重复超出扫描线
+        let mut overflow = pixels.clone();
+        overflow[4] = 137;
+        assert_eq!(
+            parse_radiance(&radiance("-Y 1 +X 8", &overflow)).unwrap_err(),
+            "run-length data overflows scanline"
+        );
+        // 长度为 0 的原样数据
+        let mut empty = pixels;
+        empty[6] = 0;
+        assert_eq!(
+            parse_radiance(&radiance("-Y 1 +X 8", &empty)).unwrap_err(),
+            "invalid run-length data"
+        );
+    }
+
+    #[test]
+    fn old_style_run_length_repeats() {
+        // 1, 1, 1, n 重复上一个像素 n 次
+        let pixels = [
+            128, 64, 32, 129, //
+            1, 1, 1, 2, //
+            16, 16, 16, 136, //
+            1, 1, 1, 1,
+        ];
+        let image = parse_radiance(&radiance("-Y 1 +X 5", &pixels)).unwrap();
+        for x in 0..3 {
+            assert_pixel(&image, x, 0, Vec3(1.0, 0.5, 0.25));
+        }
+        for x in 3..5 {
+            assert_pixel(&image, x, 0, Vec3(16.0, 16.0, 16.0));
+        }
+
+        assert_eq!(
+            parse_radiance(&radiance("-Y 1 +X 5", &pixels[4..])).unwrap_err(),
+            "run-length repeat at start of scanline"
+        );
+        assert_eq!(
+            parse_radiance(&radiance("-Y 1 +X 2", &pixels[..8])).unwrap_err(),
+            "run-length data overflows scanline"
+        );
+    }
+
+    // 两行两列：上面一行亮，下面一行暗，指数为 0 的是黑色
+    const FLAT: [u8; 16] = [
+        128, 0, 0, 129, 0, 128, 0, 129, //
+        0, 0, 128, 128, 255, 255, 255, 0,
+    ];
+
+    #[test]
+    fn flat_scanlines() {
+        let image = parse_radiance(&radiance("-Y 2 +X 2", &FLAT)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_pixel(&image, 0, 0, Vec3(1.0, 0.0, 0.0));
+        assert_pixel(&image, 1, 0, Vec3(0.0, 1.0, 0.0));
+        assert_pixel(&image, 0, 1, Vec3(0.0, 0.0, 0.5));
+        assert_pixel(&image, 1, 1, Vec3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn positive_y_is_stored_bottom_up() {
+        let image = parse_radiance(&radiance("+Y 2 +X 2", &FLAT)).unwrap();
+        assert_pixel(&image, 0, 0, Vec3(0.0, 0.0, 0.5));
+        assert_pixel(&image, 1, 0, Vec3(0.0, 0.0, 0.0));
+        assert_pixel(&image, 0, 1, Vec3(1.0, 0.0, 0.0));
+        assert_pixel(&image, 1, 1, Vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn truncated_files() {
+        let eof = "unexpected end of file";
+        // 像素数据缺了最后一个字节
+        let truncated = radiance("-Y 2 +X 2", &FLAT[..15]);
+        assert_eq!(parse_radiance(&truncated).unwrap_err(), eof);
+        // 新式 RLE 在通道中间结束
+        let truncated = radiance("-Y 1 +X 8", &[2, 2, 0, 8, 136, 128, 8, 0, 16]);
+        assert_eq!(parse_radiance(&truncated).unwrap_err(), eof);
+        // 文件头没有结束
+        assert_eq!(
+            parse_radiance(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n").unwrap_err(),
+            eof
+        );
+        assert_eq!(
+            parse_radiance(b"P6\n").unwrap_err(),
+            "missing Radiance `#?` header"
+        );
+        assert_eq!(
+            parse_radiance(&radiance("-X 2 +Y 2", &FLAT)).unwrap_err(),
+            "unsupported resolution line `-X 2 +Y 2`, expected `-Y <height> +X <width>`"
+        );
+    }
+}
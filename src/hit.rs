@@ -1,6 +1,10 @@
+use crate::aabb::Aabb;
 use crate::materials::Material;
+use crate::microfacet::Frame;
 use crate::ray::Ray;
+use crate::utils::random_f32;
 use crate::vec::Vec3;
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 pub struct Hit {
@@ -8,9 +12,14 @@ pub struct Hit {
     pub t: f32,
     // 击中的点的位置
     pub p: Vec3,
+    // 表面参数坐标
+    pub u: f32,
+    pub v: f32,
     pub normal: Option<Vec3>,
     pub front_face: Option<bool>,
     pub material: Option<Arc<dyn Material>>,
+    // 击中的物体在 `LightList` 中的序号，不是光源时为 None
+    pub light: Option<usize>,
 }
 
 impl Hit {
@@ -18,9 +27,12 @@ impl Hit {
         Hit {
             t,
             p,
+            u: 0.0,
+            v: 0.0,
             normal: None,
             front_face: None,
             material: None,
+            light: None,
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
@@ -37,6 +49,60 @@ impl Hit {
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit>;
+    // 包围盒，用于构建 BVH
+    fn aabb(&self) -> Aabb;
+
+    // 光线在 [t_min, t_max] 之间穿过物体后剩下的比例，用于阴影光线
+    // 不透明的表面只要被击中就完全遮挡；参与介质和物体集合需要重新实现
+    fn transmittance(&self, t_min: f32, t_max: f32, r: &Ray) -> f32 {
+        if self.hit(t_min, t_max, r).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    // 能否作为光源被直接采样：物体自发光，并且实现了 pdf_value 和 random
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    // 从 origin 沿 direction 看到这个物体的概率密度（对立体角），和 random 的采样方法对应
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    // 从 origin 出发随机取一个指向这个物体的方向
+    fn random(&self, _origin: Vec3) -> Vec3 {
+        Vec3(1.0, 0.0, 0.0)
+    }
+}
+
+/// 同一个物体可以同时放进场景和光源列表
+impl Hittable for Arc<dyn Hittable> {
+    fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
+        self.as_ref().hit(t_min, t_max, r)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.as_ref().aabb()
+    }
+
+    fn transmittance(&self, t_min: f32, t_max: f32, r: &Ray) -> f32 {
+        self.as_ref().transmittance(t_min, t_max, r)
+    }
+
+    fn is_light(&self) -> bool {
+        self.as_ref().is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        self.as_ref().random(origin)
+    }
 }
 
 pub struct Sphere {
@@ -45,39 +111,454 @@ pub struct Sphere {
     pub material: Arc<dyn Material>,
 }
 
+impl Sphere {
+    /// 单位球面上的点对应的 (u, v)
+    /// u 从 x = -1 开始绕 y 轴一圈，v 从 y = -1 到 y = 1
+    pub fn uv(p: Vec3) -> (f32, f32) {
+        let theta = libm::acosf((-p.y()).clamp(-1.0, 1.0));
+        let phi = libm::atan2f(-p.z(), p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.length_squared();
-        let half_b = oc.dot(ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
+        hit_sphere(self.center, self.radius, &self.material, t_min, t_max, ray)
+    }
+
+    fn aabb(&self) -> Aabb {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    // 在球外时均匀采样球所张的圆锥，在球内时均匀采样所有方向
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        if self
+            .hit(0.001, f32::MAX, &Ray::new(origin, direction))
+            .is_none()
+        {
+            return 0.0;
+        }
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+            None => 1.0 / (4.0 * PI),
+        }
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let cos_theta_max = match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return Vec3::random_unit_vector(),
+        };
+        let z = 1.0 + random_f32() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * random_f32();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let frame = Frame::from_normal((self.center - origin).to_unit_vector());
+        frame.to_world(Vec3(r * libm::cosf(phi), r * libm::sinf(phi), z))
+    }
+}
+
+impl Sphere {
+    // 从 origin 看过去球所张圆锥的半角的余弦，origin 在球内时返回 None
+    fn cos_theta_max(&self, origin: Vec3) -> Option<f32> {
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
             return None;
         }
-        let sqrt_d = discriminant.sqrt();
-        // Find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrt_d) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
+        Some((1.0 - radius_squared / distance_squared).sqrt())
+    }
+}
+
+/// 在 `time0` 到 `time1` 之间从 `center0` 匀速移动到 `center1` 的球
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f32) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let t = (time - self.time0) / (self.time1 - self.time0);
+        self.center0 + t * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let center = self.center(ray.time);
+        hit_sphere(center, self.radius, &self.material, t_min, t_max, ray)
+    }
+
+    // 包围盒覆盖整个运动过程
+    fn aabb(&self) -> Aabb {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        Aabb::new(self.center0 - r, self.center0 + r)
+            .union(&Aabb::new(self.center1 - r, self.center1 + r))
+    }
+
+    // `pdf_value` 和 `random` 不知道光线的时间，发光的运动球不会加入光源列表，
+    // 只能靠散射光线击中
+}
+
+/// 平行于 xy 平面的矩形，位于 z = k
+pub struct XyRect {
+    pub x0: f32,
+    pub x1: f32,
+    pub y0: f32,
+    pub y1: f32,
+    pub k: f32,
+    pub material: Arc<dyn Material>,
+}
+
+/// 平行于 xz 平面的矩形，位于 y = k
+pub struct XzRect {
+    pub x0: f32,
+    pub x1: f32,
+    pub z0: f32,
+    pub z1: f32,
+    pub k: f32,
+    pub material: Arc<dyn Material>,
+}
+
+/// 平行于 yz 平面的矩形，位于 x = k
+pub struct YzRect {
+    pub y0: f32,
+    pub y1: f32,
+    pub z0: f32,
+    pub z1: f32,
+    pub k: f32,
+    pub material: Arc<dyn Material>,
+}
+
+impl Hittable for XyRect {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let bounds = [(self.x0, self.x1), (self.y0, self.y1)];
+        hit_rect(ray, t_min, t_max, [0, 1, 2], bounds, self.k, &self.material)
+    }
+
+    fn aabb(&self) -> Aabb {
+        rect_aabb([0, 1, 2], [(self.x0, self.x1), (self.y0, self.y1)], self.k)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf(self, origin, direction, area)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (q, u, v) = rect_edges([0, 1, 2], [(self.x0, self.x1), (self.y0, self.y1)], self.k);
+        q + random_f32() * u + random_f32() * v - origin
+    }
+}
+
+impl Hittable for XzRect {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let bounds = [(self.x0, self.x1), (self.z0, self.z1)];
+        hit_rect(ray, t_min, t_max, [0, 2, 1], bounds, self.k, &self.material)
+    }
+
+    fn aabb(&self) -> Aabb {
+        rect_aabb([0, 2, 1], [(self.x0, self.x1), (self.z0, self.z1)], self.k)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        area_pdf(self, origin, direction, area)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (q, u, v) = rect_edges([0, 2, 1], [(self.x0, self.x1), (self.z0, self.z1)], self.k);
+        q + random_f32() * u + random_f32() * v - origin
+    }
+}
+
+impl Hittable for YzRect {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let bounds = [(self.y0, self.y1), (self.z0, self.z1)];
+        hit_rect(ray, t_min, t_max, [1, 2, 0], bounds, self.k, &self.material)
+    }
+
+    fn aabb(&self) -> Aabb {
+        rect_aabb([1, 2, 0], [(self.y0, self.y1), (self.z0, self.z1)], self.k)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        area_pdf(self, origin, direction, area)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (q, u, v) = rect_edges([1, 2, 0], [(self.y0, self.y1), (self.z0, self.z1)], self.k);
+        q + random_f32() * u + random_f32() * v - origin
+    }
+}
+
+fn axis_vector(axis: usize, value: f32) -> Vec3 {
+    let mut v = [0.0; 3];
+    v[axis] = value;
+    Vec3(v[0], v[1], v[2])
+}
+
+/// 矩形的两条边沿 `axes[0]`、`axes[1]` 轴，所在平面垂直于 `axes[2]` 轴
+fn hit_rect(
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    axes: [usize; 3],
+    bounds: [(f32, f32); 2],
+    k: f32,
+    material: &Arc<dyn Material>,
+) -> Option<Hit> {
+    let [a, b, c] = axes;
+    let t = (k - ray.origin[c]) / ray.direction[c];
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+    let p = ray.at(t);
+    let [(a0, a1), (b0, b1)] = bounds;
+    if p[a] < a0 || p[a] > a1 || p[b] < b0 || p[b] > b1 {
+        return None;
+    }
+    let mut hit = Hit::new(t, p);
+    hit.u = (p[a] - a0) / (a1 - a0);
+    hit.v = (p[b] - b0) / (b1 - b0);
+    hit.set_face_normal(ray, axis_vector(c, 1.0));
+    hit.material = Some(material.clone());
+    Some(hit)
+}
+
+/// 矩形的一个顶点和两条边
+fn rect_edges(axes: [usize; 3], bounds: [(f32, f32); 2], k: f32) -> (Vec3, Vec3, Vec3) {
+    let [a, b, c] = axes;
+    let [(a0, a1), (b0, b1)] = bounds;
+    (
+        axis_vector(a, a0) + axis_vector(b, b0) + axis_vector(c, k),
+        axis_vector(a, a1 - a0),
+        axis_vector(b, b1 - b0),
+    )
+}
+
+/// 在面积为 `area` 的平面图形上均匀采样时，对应到立体角上的概率密度
+fn area_pdf(shape: &dyn Hittable, origin: Vec3, direction: Vec3, area: f32) -> f32 {
+    let ray = Ray::new(origin, direction);
+    let rec = match shape.hit(0.001, f32::MAX, &ray) {
+        Some(rec) => rec,
+        None => return 0.0,
+    };
+    let distance_squared = rec.t * rec.t * direction.length_squared();
+    let cosine = rec.normal.unwrap().dot(direction).abs() / direction.length();
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+fn rect_aabb(axes: [usize; 3], bounds: [(f32, f32); 2], k: f32) -> Aabb {
+    let [a, b, c] = axes;
+    let [(a0, a1), (b0, b1)] = bounds;
+    // 矩形的包围盒在法线方向上厚度为 0，稍微撑开一点
+    Aabb::new(
+        axis_vector(a, a0) + axis_vector(b, b0) + axis_vector(c, k - 1e-4),
+        axis_vector(a, a1) + axis_vector(b, b1) + axis_vector(c, k + 1e-4),
+    )
+}
+
+/// 平行四边形，由一个顶点 `q` 和两条边 `u`、`v` 确定
+/// 法线方向为 u × v，表面坐标 (u, v) 是沿两条边的比例
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // 平面方程 normal · p = d
+    d: f32,
+    // 用来求点在两条边方向上的坐标
+    w: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Quad {
+        let n = u.cross(v);
+        let normal = n.to_unit_vector();
+        Quad {
+            q,
+            u,
+            v,
+            normal,
+            d: normal.dot(q),
+            w: n / n.length_squared(),
+            material,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let denom = self.normal.dot(ray.direction);
+        // 光线和平面平行
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(ray.origin)) / denom;
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
         }
-        let t = root;
-        let p = ray.at(root);
         let mut hit = Hit::new(t, p);
-        let outward_normal = (p - self.center) / self.radius;
-        hit.set_face_normal(ray, outward_normal);
+        hit.u = alpha;
+        hit.v = beta;
+        hit.set_face_normal(ray, self.normal);
         hit.material = Some(self.material.clone());
         Some(hit)
     }
+
+    fn aabb(&self) -> Aabb {
+        let padding = Vec3(1e-4, 1e-4, 1e-4);
+        let bbox = Aabb::from_points(self.q, self.q + self.u + self.v)
+            .grow(self.q + self.u)
+            .grow(self.q + self.v);
+        Aabb::new(bbox.min - padding, bbox.max + padding)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        area_pdf(self, origin, direction, self.u.cross(self.v).length())
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        self.q + random_f32() * self.u + random_f32() * self.v - origin
+    }
+}
+
+/// 和坐标轴对齐的长方体，由六个 `Quad` 组成，法线朝外
+/// 不叫 `Box` 是为了不和标准库的 `Box` 冲突
+pub struct Cuboid {
+    sides: HittableList,
+}
+
+impl Cuboid {
+    /// `a` 和 `b` 是长方体的两个对角顶点
+    pub fn new(a: Vec3, b: Vec3, material: Arc<dyn Material>) -> Cuboid {
+        let min = a.min(b);
+        let max = a.max(b);
+        let dx = Vec3(max.x() - min.x(), 0.0, 0.0);
+        let dy = Vec3(0.0, max.y() - min.y(), 0.0);
+        let dz = Vec3(0.0, 0.0, max.z() - min.z());
+        let mut sides = HittableList::new();
+        let mut add = |q: Vec3, u: Vec3, v: Vec3| {
+            sides.add(Box::new(Quad::new(q, u, v, material.clone())));
+        };
+        // 前 (+z)、右 (+x)、后 (-z)、左 (-x)、上 (+y)、下 (-y)
+        add(Vec3(min.x(), min.y(), max.z()), dx, dy);
+        add(Vec3(max.x(), min.y(), max.z()), -dz, dy);
+        add(Vec3(max.x(), min.y(), min.z()), -dx, dy);
+        add(Vec3(min.x(), min.y(), min.z()), dz, dy);
+        add(Vec3(min.x(), max.y(), max.z()), dx, -dz);
+        add(Vec3(min.x(), min.y(), min.z()), dx, dz);
+        Cuboid { sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        self.sides.hit(t_min, t_max, ray)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.sides.aabb()
+    }
+
+    fn is_light(&self) -> bool {
+        self.sides.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        self.sides.random(origin)
+    }
+}
+
+fn hit_sphere(
+    center: Vec3,
+    radius: f32,
+    material: &Arc<dyn Material>,
+    t_min: f32,
+    t_max: f32,
+    ray: &Ray,
+) -> Option<Hit> {
+    let oc = ray.origin - center;
+    let a = ray.direction.length_squared();
+    let half_b = oc.dot(ray.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt_d = discriminant.sqrt();
+    // Find the nearest root that lies in the acceptable range.
+    let mut root = (-half_b - sqrt_d) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + sqrt_d) / a;
+        if root < t_min || t_max < root {
+            return None;
+        }
+    }
+    let t = root;
+    let p = ray.at(root);
+    let mut hit = Hit::new(t, p);
+    let outward_normal = (p - center) / radius;
+    hit.set_face_normal(ray, outward_normal);
+    (hit.u, hit.v) = Sphere::uv(outward_normal);
+    hit.material = Some(material.clone());
+    Some(hit)
 }
 
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
 }
 
+impl Default for HittableList {
+    fn default() -> HittableList {
+        HittableList::new()
+    }
+}
+
 impl HittableList {
     pub fn new() -> HittableList {
         HittableList { objects: vec![] }
@@ -93,6 +574,17 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
+    fn transmittance(&self, t_min: f32, t_max: f32, r: &Ray) -> f32 {
+        let mut transmittance = 1.0;
+        for object in &self.objects {
+            transmittance *= object.transmittance(t_min, t_max, r);
+            if transmittance <= 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+
     fn hit(&self, t_min: f32, t_max: f32, r: &Ray) -> Option<Hit> {
         let mut temp_rec: Option<Hit> = None;
         let mut closest_so_far = t_max;
@@ -106,4 +598,225 @@ impl Hittable for HittableList {
         }
         temp_rec
     }
+
+    fn aabb(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::empty(), |acc, object| acc.union(&object.aabb()))
+    }
+
+    // 所有物体都是光源时，整个集合才能作为一个光源
+    fn is_light(&self) -> bool {
+        !self.objects.is_empty() && self.objects.iter().all(|object| object.is_light())
+    }
+
+    // 均匀选择一个物体采样，概率密度是各个物体的平均
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f32
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let n = self.objects.len();
+        if n == 0 {
+            return Vec3(1.0, 0.0, 0.0);
+        }
+        let index = ((random_f32() * n as f32) as usize).min(n - 1);
+        self.objects[index].random(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)))
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn moving_sphere_follows_time_and_aabb_covers_the_motion() {
+        let sphere = MovingSphere {
+            center0: Vec3(0.0, 0.0, 0.0),
+            center1: Vec3(4.0, 2.0, 0.0),
+            time0: 0.0,
+            time1: 1.0,
+            radius: 0.5,
+            material: material(),
+        };
+        let bbox = sphere.aabb();
+        assert_close(bbox.min, Vec3(-0.5, -0.5, -0.5));
+        assert_close(bbox.max, Vec3(4.5, 2.5, 0.5));
+        for time in [0.0, 0.25, 0.5, 1.0] {
+            let center = sphere.center(time);
+            assert_close(center, Vec3(4.0 * time, 2.0 * time, 0.0));
+            // 沿 -z 方向射向当前时刻的球心
+            let origin = center + Vec3(0.0, 0.0, 5.0);
+            let ray = Ray::with_time(origin, Vec3(0.0, 0.0, -1.0), time);
+            let hit = sphere.hit(0.001, f32::MAX, &ray).unwrap();
+            assert!((hit.t - 4.5).abs() < 1e-4, "t = {} at time {}", hit.t, time);
+            assert_close(hit.normal.unwrap(), Vec3(0.0, 0.0, 1.0));
+            // 同一条光线在另一个时刻射不中
+            let later = Ray::with_time(origin, Vec3(0.0, 0.0, -1.0), 1.0 - time);
+            if (time - 0.5).abs() > 0.1 {
+                assert!(sphere.hit(0.001, f32::MAX, &later).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn axis_aligned_rects_report_hits_normals_and_uvs() {
+        // 矩形、两条边的方向和法线所在的轴
+        let rects: [(&str, Box<dyn Hittable>, [Vec3; 3]); 3] = [
+            (
+                "xy",
+                Box::new(XyRect {
+                    x0: 0.0,
+                    x1: 2.0,
+                    y0: 0.0,
+                    y1: 1.0,
+                    k: -3.0,
+                    material: material(),
+                }),
+                [
+                    Vec3(1.0, 0.0, 0.0),
+                    Vec3(0.0, 1.0, 0.0),
+                    Vec3(0.0, 0.0, 1.0),
+                ],
+            ),
+            (
+                "xz",
+                Box::new(XzRect {
+                    x0: 0.0,
+                    x1: 2.0,
+                    z0: 0.0,
+                    z1: 1.0,
+                    k: -3.0,
+                    material: material(),
+                }),
+                [
+                    Vec3(1.0, 0.0, 0.0),
+                    Vec3(0.0, 0.0, 1.0),
+                    Vec3(0.0, 1.0, 0.0),
+                ],
+            ),
+            (
+                "yz",
+                Box::new(YzRect {
+                    y0: 0.0,
+                    y1: 2.0,
+                    z0: 0.0,
+                    z1: 1.0,
+                    k: -3.0,
+                    material: material(),
+                }),
+                [
+                    Vec3(0.0, 1.0, 0.0),
+                    Vec3(0.0, 0.0, 1.0),
+                    Vec3(1.0, 0.0, 0.0),
+                ],
+            ),
+        ];
+        for (name, rect, [a, b, axis]) in &rects {
+            let (a, b, axis) = (*a, *b, *axis);
+            // 矩形上的点 (1.5, 0.25)，u、v 是沿两条边的比例
+            let point = 1.5 * a + 0.25 * b - 3.0 * axis;
+            for side in [1.0, -1.0] {
+                let ray = Ray::new(point + 2.0 * side * axis, -side * axis);
+                let hit = rect.hit(0.001, f32::MAX, &ray).unwrap();
+                assert!((hit.t - 2.0).abs() < 1e-4, "{}: t = {}", name, hit.t);
+                assert_close(hit.p, point);
+                assert!((hit.u - 0.75).abs() < 1e-5 && (hit.v - 0.25).abs() < 1e-5);
+                // 法线总是朝向光线射来的一侧
+                assert_close(hit.normal.unwrap(), side * axis);
+                assert_eq!(hit.front_face, Some(side > 0.0), "{}", name);
+            }
+            let outside = Ray::new(point + 2.0 * a + axis, -axis);
+            assert!(rect.hit(0.001, f32::MAX, &outside).is_none(), "{}", name);
+            let parallel = Ray::new(point + axis, a);
+            assert!(rect.hit(0.001, f32::MAX, &parallel).is_none(), "{}", name);
+            let bbox = rect.aabb();
+            assert!(bbox.extent().length() > 2.0 && !bbox.is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn quads_use_edge_coordinates() {
+        let quad = Quad::new(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(2.0, 0.0, 0.0),
+            Vec3(1.0, 0.0, -1.0),
+            material(),
+        );
+        // 平行四边形上 q + 0.5 u + 0.25 v 处的点
+        let point = Vec3(1.25, 0.0, -0.25);
+        let hit = quad
+            .hit(
+                0.001,
+                f32::MAX,
+                &Ray::new(point + Vec3(0.0, 3.0, 0.0), Vec3(0.0, -1.0, 0.0)),
+            )
+            .unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-4);
+        assert!((hit.u - 0.5).abs() < 1e-5 && (hit.v - 0.25).abs() < 1e-5);
+        assert_close(hit.normal.unwrap(), Vec3(0.0, 1.0, 0.0));
+        assert_eq!(hit.front_face, Some(true));
+
+        let below = Ray::new(point - Vec3(0.0, 3.0, 0.0), Vec3(0.0, 1.0, 0.0));
+        let hit = quad.hit(0.001, f32::MAX, &below).unwrap();
+        assert_close(hit.normal.unwrap(), Vec3(0.0, -1.0, 0.0));
+        assert_eq!(hit.front_face, Some(false));
+
+        // 在包围盒里但在平行四边形外
+        let outside = Ray::new(Vec3(0.2, 3.0, -0.8), Vec3(0.0, -1.0, 0.0));
+        assert!(quad.hit(0.001, f32::MAX, &outside).is_none());
+        assert!(quad
+            .hit(
+                0.001,
+                2.0,
+                &Ray::new(point + Vec3(0.0, 3.0, 0.0), Vec3(0.0, -1.0, 0.0))
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn boxes_have_outward_normals() {
+        let cuboid = Cuboid::new(Vec3(1.0, 2.0, 3.0), Vec3(-1.0, -1.0, -1.0), material());
+        // 包围盒由各个面的包围盒合并而成，每个面都稍微撑开了一点
+        let bbox = cuboid.aabb();
+        assert!((bbox.min - Vec3(-1.0, -1.0, -1.0)).length() < 1e-3);
+        assert!((bbox.max - Vec3(1.0, 2.0, 3.0)).length() < 1e-3);
+        let center = Vec3(0.0, 0.5, 1.0);
+        let half = Vec3(1.0, 1.5, 2.0);
+        for axis in 0..3 {
+            for side in [1.0, -1.0] {
+                let normal = axis_vector(axis, side);
+                let ray = Ray::new(center + 10.0 * normal, -normal);
+                let hit = cuboid.hit(0.001, f32::MAX, &ray).unwrap();
+                assert!((hit.t - (10.0 - half[axis])).abs() < 1e-4, "{:?}", normal);
+                assert_close(hit.normal.unwrap(), normal);
+                assert_eq!(hit.front_face, Some(true));
+                // 从内部射出时击中同一个面的背面
+                let inside = Ray::new(center, normal);
+                let hit = cuboid.hit(0.001, f32::MAX, &inside).unwrap();
+                assert!((hit.t - half[axis]).abs() < 1e-4);
+                assert_close(hit.normal.unwrap(), -normal);
+                assert_eq!(hit.front_face, Some(false));
+            }
+        }
+        let miss = Ray::new(Vec3(1.5, 0.5, 10.0), Vec3(0.0, 0.0, -1.0));
+        assert!(cuboid.hit(0.001, f32::MAX, &miss).is_none());
+    }
 }
@@ -0,0 +1,50 @@
+//! 跟着 Ray Tracing in One Weekend 写的光线追踪器
+//!
+//! 用 [`Renderer`] 把一个实现了 [`Hittable`] 的场景从 [`Camera`] 的视角渲染成浮点帧缓冲：
+//!
+//! ```no_run
+//! use raytrace::scene::{random_scene, CameraSettings};
+//! use raytrace::{BvhNode, RenderSettings, Renderer};
+//!
+//! let settings = RenderSettings::default();
+//! let world = BvhNode::new(random_scene().objects);
+//! let camera = CameraSettings::default().build(settings.aspect_ratio);
+//! let framebuffer = Renderer::new(settings).render(&world, &camera);
+//! ```
+
+pub mod aabb;
+pub mod background;
+pub mod bvh;
+pub mod camera;
+pub mod distribution;
+pub mod hdr;
+pub mod hit;
+pub mod light;
+pub mod materials;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
+pub mod obj;
+pub mod output;
+pub mod perlin;
+pub mod ray;
+pub mod renderer;
+pub mod scene;
+pub mod sky;
+pub mod texture;
+pub mod tonemap;
+pub mod transform;
+pub mod utils;
+pub mod vec;
+
+pub use crate::background::Background;
+pub use crate::bvh::BvhNode;
+pub use crate::camera::Camera;
+pub use crate::hit::{Hit, Hittable, HittableList};
+pub use crate::light::{AnalyticLight, LightList};
+pub use crate::materials::Material;
+pub use crate::ray::Ray;
+pub use crate::renderer::{Framebuffer, RenderSettings, Renderer};
+pub use crate::texture::Texture;
+pub use crate::transform::{Mat4, Transform};
+pub use crate::vec::Vec3;
@@ -0,0 +1,604 @@
+use crate::aabb::Aabb;
+use crate::hit::{Hit, Hittable};
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::utils::{degrees_to_radians, random_f32};
+use crate::vec::Vec3;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// 没有几何形状的解析光源，相机看不到点光源和聚光灯，只能通过阴影光线照亮场景
+/// `direction` 都是光传播的方向
+#[derive(Clone, Copy, Debug)]
+pub enum AnalyticLight {
+    /// 点光源，`intensity` 是辐射强度，照度随距离的平方衰减
+    Point { position: Vec3, intensity: Vec3 },
+    /// 聚光灯，夹角的余弦小于 `cos_cone` 时没有光，大于 `cos_falloff` 时是全部强度，中间平滑过渡
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cos_cone: f32,
+        cos_falloff: f32,
+    },
+    /// 平行光，`irradiance` 是垂直于光线的平面上的照度
+    /// `angular_radius`（弧度）大于 0 时是一个亮度均匀的圆盘，例如太阳
+    Directional {
+        direction: Vec3,
+        irradiance: Vec3,
+        angular_radius: f32,
+    },
+}
+
+/// 从某一点朝光源采样的结果
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    /// 指向光源的单位向量
+    pub direction: Vec3,
+    /// 到光源的距离，平行光是 `f32::MAX`
+    pub distance: f32,
+    /// 沿 `direction` 到达的辐射亮度，delta 光源是照度
+    pub radiance: Vec3,
+    /// 立体角上的概率密度，delta 光源是离散的概率
+    pub pdf: f32,
+    /// 点光源、聚光灯和没有大小的平行光只能靠光源采样找到
+    pub delta: bool,
+}
+
+impl AnalyticLight {
+    pub fn point(position: Vec3, intensity: Vec3) -> AnalyticLight {
+        AnalyticLight::Point {
+            position,
+            intensity,
+        }
+    }
+
+    /// `cone_angle` 是光锥的半角，`falloff_angle` 是边缘处强度逐渐减弱的宽度，单位都是度
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cone_angle: f32,
+        falloff_angle: f32,
+    ) -> AnalyticLight {
+        let falloff_angle = falloff_angle.clamp(0.0, cone_angle);
+        AnalyticLight::Spot {
+            position,
+            direction: direction.to_unit_vector(),
+            intensity,
+            cos_cone: libm::cosf(degrees_to_radians(cone_angle)),
+            cos_falloff: libm::cosf(degrees_to_radians(cone_angle - falloff_angle)),
+        }
+    }
+
+    /// `angular_diameter` 是光源的视直径，单位是度，太阳约为 0.53
+    pub fn directional(direction: Vec3, irradiance: Vec3, angular_diameter: f32) -> AnalyticLight {
+        AnalyticLight::Directional {
+            direction: direction.to_unit_vector(),
+            irradiance,
+            angular_radius: degrees_to_radians(0.5 * angular_diameter),
+        }
+    }
+
+    pub fn is_delta(&self) -> bool {
+        match self {
+            AnalyticLight::Directional { angular_radius, .. } => *angular_radius <= 0.0,
+            _ => true,
+        }
+    }
+
+    /// 从 `origin` 朝光源采样一个方向，照不到 `origin` 时返回 None
+    pub fn sample(&self, origin: Vec3) -> Option<LightSample> {
+        match *self {
+            AnalyticLight::Point {
+                position,
+                intensity,
+            } => delta_sample(origin, position, intensity),
+            AnalyticLight::Spot {
+                position,
+                direction,
+                intensity,
+                cos_cone,
+                cos_falloff,
+            } => {
+                let mut sample = delta_sample(origin, position, intensity)?;
+                let cos = -sample.direction.dot(direction);
+                let falloff = if cos >= cos_falloff {
+                    1.0
+                } else if cos <= cos_cone {
+                    0.0
+                } else {
+                    let t = (cos - cos_cone) / (cos_falloff - cos_cone);
+                    t * t * (3.0 - 2.0 * t)
+                };
+                if falloff <= 0.0 {
+                    return None;
+                }
+                sample.radiance = falloff * sample.radiance;
+                Some(sample)
+            }
+            AnalyticLight::Directional {
+                direction,
+                irradiance,
+                angular_radius,
+            } => {
+                if angular_radius <= 0.0 {
+                    return Some(LightSample {
+                        direction: -direction,
+                        distance: f32::MAX,
+                        radiance: irradiance,
+                        pdf: 1.0,
+                        delta: true,
+                    });
+                }
+                // 在光锥内均匀采样，直接用 1 - cos 计算，角半径很小时也不丢失精度
+                let cap = cone_cap(angular_radius);
+                let one_minus_z = random_f32() * cap;
+                let r = (one_minus_z * (2.0 - one_minus_z)).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f32();
+                let frame = Frame::from_normal(-direction);
+                let local = Vec3(r * libm::cosf(phi), r * libm::sinf(phi), 1.0 - one_minus_z);
+                Some(LightSample {
+                    direction: frame.to_world(local).to_unit_vector(),
+                    distance: f32::MAX,
+                    radiance: disk_radiance(irradiance, angular_radius),
+                    pdf: 1.0 / (2.0 * PI * cap),
+                    delta: false,
+                })
+            }
+        }
+    }
+
+    /// 光线没有击中任何物体时沿 `direction` 看到的辐射亮度，以及 `sample` 采到这个方向的概率密度
+    /// 只有有大小的平行光能被看到
+    pub fn escaped(&self, direction: Vec3) -> Option<(Vec3, f32)> {
+        match *self {
+            AnalyticLight::Directional {
+                direction: light_direction,
+                irradiance,
+                angular_radius,
+            } if angular_radius > 0.0 => {
+                let direction = direction.to_unit_vector();
+                // 用叉积判断夹角，角半径很小时比比较余弦准确
+                let inside = direction.dot(light_direction) < 0.0
+                    && direction.cross(light_direction).length() <= libm::sinf(angular_radius);
+                inside.then(|| {
+                    (
+                        disk_radiance(irradiance, angular_radius),
+                        1.0 / (2.0 * PI * cone_cap(angular_radius)),
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 位于 `position` 的点光源照到 `origin` 的结果
+fn delta_sample(origin: Vec3, position: Vec3, intensity: Vec3) -> Option<LightSample> {
+    let offset = position - origin;
+    let distance_squared = offset.length_squared();
+    if distance_squared <= 0.0 {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    Some(LightSample {
+        direction: offset / distance,
+        distance,
+        radiance: intensity / distance_squared,
+        pdf: 1.0,
+        delta: true,
+    })
+}
+
+/// 半角为 `angular_radius` 的光锥对应的 1 - cos，光锥的立体角是它的 2π 倍
+fn cone_cap(angular_radius: f32) -> f32 {
+    let s = libm::sinf(0.5 * angular_radius);
+    2.0 * s * s
+}
+
+/// 圆盘的照度是 L π sin²θ，由照度反推亮度
+fn disk_radiance(irradiance: Vec3, angular_radius: f32) -> Vec3 {
+    let s = libm::sinf(angular_radius);
+    irradiance / (PI * s * s)
+}
+
+/// 可以直接采样的光源，用于下一事件估计（每个散射点都朝光源发一条阴影光线）
+/// 发光的物体同时也要放在场景里，光线才能击中它们。
+#[derive(Clone, Default)]
+pub struct LightList {
+    lights: Vec<Arc<dyn Hittable>>,
+    analytic: Vec<AnalyticLight>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Arc<dyn Hittable>>) -> LightList {
+        LightList {
+            lights,
+            analytic: vec![],
+        }
+    }
+
+    /// 把 `objects` 中可以作为光源的物体挑出来，它们用 `Arc` 在场景和光源列表之间共享
+    /// 场景中的光源会在击中记录里写上自己的序号，`pdf` 不用再逐个求交
+    pub fn extract(objects: Vec<Box<dyn Hittable>>) -> (Vec<Box<dyn Hittable>>, LightList) {
+        let mut lights = vec![];
+        let objects = objects
+            .into_iter()
+            .map(|object| {
+                if !object.is_light() {
+                    return object;
+                }
+                let light: Arc<dyn Hittable> = Arc::from(object);
+                let index = lights.len();
+                lights.push(light.clone());
+                Box::new(Tagged {
+                    object: light,
+                    index,
+                }) as Box<dyn Hittable>
+            })
+            .collect();
+        (objects, LightList::new(lights))
+    }
+
+    /// 加入解析光源
+    pub fn with_analytic(mut self, lights: Vec<AnalyticLight>) -> LightList {
+        self.analytic.extend(lights);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len() + self.analytic.len()
+    }
+
+    /// 均匀选择一个光源，从 `origin` 朝它采样一个方向，`time` 用来和运动的光源求交
+    /// 返回的概率密度包含选择光源的概率
+    pub fn sample(&self, origin: Vec3, time: f32) -> Option<LightSample> {
+        let n = self.len();
+        if n == 0 {
+            return None;
+        }
+        let index = ((random_f32() * n as f32) as usize).min(n - 1);
+        let mut sample = match self.lights.get(index) {
+            Some(light) => {
+                let direction = light.random(origin).to_unit_vector();
+                let pdf = light.pdf_value(origin, direction);
+                if !(pdf > 0.0 && pdf.is_finite()) {
+                    return None;
+                }
+                let rec = light.hit(0.001, f32::MAX, &Ray::with_time(origin, direction, time))?;
+                LightSample {
+                    direction,
+                    distance: rec.t,
+                    radiance: rec.material.unwrap().emitted(rec.u, rec.v, rec.p),
+                    pdf,
+                    delta: false,
+                }
+            }
+            None => self.analytic[index - self.lights.len()].sample(origin)?,
+        };
+        sample.pdf /= n as f32;
+        Some(sample)
+    }
+
+    /// 光线击中了某个光源时，用 `sample` 采到这个方向的概率密度，击中的不是光源时返回 0
+    /// `extract` 给出的光源直接按序号查找，其他的（例如用 `new` 创建的）逐个求交
+    pub fn pdf(&self, ray: &Ray, rec: &Hit) -> f32 {
+        let light = match rec.light {
+            Some(index) => self.lights.get(index),
+            None => {
+                let tolerance = 1e-4 * rec.t.max(1.0);
+                self.lights.iter().find(|light| {
+                    light
+                        .hit(0.001, f32::MAX, ray)
+                        .is_some_and(|hit| (hit.t - rec.t).abs() <= tolerance)
+                })
+            }
+        };
+        light.map_or(0.0, |light| {
+            light.pdf_value(ray.origin, ray.direction) / self.len() as f32
+        })
+    }
+
+    /// 光线没有击中任何物体时看到的每个解析光源的辐射亮度，以及用 `sample` 采到这个方向的概率密度
+    pub fn escaped(&self, direction: Vec3) -> impl Iterator<Item = (Vec3, f32)> + '_ {
+        let n = self.len() as f32;
+        self.analytic
+            .iter()
+            .filter_map(move |light| light.escaped(direction))
+            .map(move |(radiance, pdf)| (radiance, pdf / n))
+    }
+}
+
+/// 场景中的光源，击中时记下它在 `LightList` 中的序号
+struct Tagged {
+    object: Arc<dyn Hittable>,
+    index: usize,
+}
+
+impl Hittable for Tagged {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let mut rec = self.object.hit(t_min, t_max, ray)?;
+        rec.light = Some(self.index);
+        Some(rec)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.object.aabb()
+    }
+
+    fn transmittance(&self, t_min: f32, t_max: f32, ray: &Ray) -> f32 {
+        self.object.transmittance(t_min, t_max, ray)
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        self.object.random(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::{HittableList, Quad, Sphere, XzRect};
+    use crate::materials::{DiffuseLight, Lambertian};
+    use crate::mesh::{Triangle, TriangleMesh};
+    use crate::transform::Transform;
+    use crate::utils::seed_rng;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn light_pdfs_integrate_to_one() {
+        seed_rng(8);
+        let material = Arc::new(DiffuseLight::new(Vec3(4.0, 4.0, 4.0)));
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: material.clone(),
+        });
+        let lights: Vec<(&str, Box<dyn Hittable>)> = vec![
+            ("sphere", Box::new(sphere.clone())),
+            (
+                "rect",
+                Box::new(XzRect {
+                    x0: -1.0,
+                    x1: 2.0,
+                    z0: -1.0,
+                    z1: 1.0,
+                    k: 0.5,
+                    material: material.clone(),
+                }),
+            ),
+            (
+                "quad",
+                Box::new(Quad::new(
+                    Vec3(-1.0, -1.0, 1.0),
+                    Vec3(2.0, 0.5, 0.0),
+                    Vec3(0.0, 1.5, -0.5),
+                    material.clone(),
+                )),
+            ),
+            (
+                // 着色法线和几何法线不同，pdf 要用几何法线
+                "triangle",
+                Box::new(Triangle {
+                    mesh: Arc::new(
+                        TriangleMesh::new(
+                            vec![
+                                Vec3(-1.0, -1.0, 1.5),
+                                Vec3(2.0, 0.0, 1.2),
+                                Vec3(0.0, 2.0, 1.8),
+                            ],
+                            vec![
+                                Vec3(1.0, 0.0, 1.0),
+                                Vec3(0.0, 1.0, 1.0),
+                                Vec3(0.0, 0.0, 1.0),
+                            ],
+                            vec![],
+                            vec![[0, 1, 2]],
+                            material,
+                        )
+                        .unwrap(),
+                    ),
+                    index: 0,
+                }),
+            ),
+            (
+                "transformed sphere",
+                Box::new(
+                    Transform::new(sphere)
+                        .scale(Vec3(2.0, 0.5, 1.0))
+                        .rotate_z(30.0)
+                        .translate(Vec3(0.5, 0.0, 0.0)),
+                ),
+            ),
+        ];
+        // 第二个原点在球的内部
+        for origin in [Vec3(0.3, 3.0, 2.5), Vec3(0.2, 0.1, 0.0)] {
+            for (name, light) in &lights {
+                assert!(light.is_light(), "{}", name);
+                let count = 200_000;
+                let mut integral = 0.0f64;
+                for _ in 0..count {
+                    let direction = Vec3::random_unit_vector();
+                    integral += (4.0 * PI * light.pdf_value(origin, direction)) as f64;
+                }
+                let integral = (integral / count as f64) as f32;
+                assert!(
+                    (integral - 1.0).abs() < 0.03,
+                    "{} from {:?}: ∫ pdf = {}",
+                    name,
+                    origin,
+                    integral
+                );
+                for _ in 0..1000 {
+                    let direction = light.random(origin);
+                    let ray = Ray::new(origin, direction);
+                    assert!(light.hit(0.001, f32::MAX, &ray).is_some(), "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_finds_the_hit_light_by_its_tag() {
+        seed_rng(13);
+        let light = Arc::new(DiffuseLight::new(Vec3(4.0, 4.0, 4.0)));
+        let objects: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Sphere {
+                center: Vec3(0.0, -101.0, 0.0),
+                radius: 100.0,
+                material: Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5))),
+            }),
+            Box::new(Sphere {
+                center: Vec3(-1.5, 0.0, 0.0),
+                radius: 0.5,
+                material: light.clone(),
+            }),
+            Box::new(XzRect {
+                x0: 0.0,
+                x1: 2.0,
+                z0: -1.0,
+                z1: 1.0,
+                k: 1.0,
+                material: light,
+            }),
+        ];
+        let (objects, lights) = LightList::extract(objects);
+        assert_eq!(lights.len(), 2);
+        let world = HittableList { objects };
+        let origin = Vec3(0.0, 0.0, 3.0);
+        let mut light_hits = 0;
+        for _ in 0..2_000 {
+            let ray = Ray::new(origin, Vec3::random_unit_vector());
+            let mut rec = match world.hit(0.001, f32::MAX, &ray) {
+                Some(rec) => rec,
+                None => continue,
+            };
+            let pdf = lights.pdf(&ray, &rec);
+            let emissive = rec.material.as_ref().unwrap().is_emissive();
+            assert_eq!(rec.light.is_some(), emissive);
+            if emissive {
+                light_hits += 1;
+                assert!(pdf > 0.0);
+            } else {
+                assert_eq!(pdf, 0.0);
+            }
+            // 没有序号时逐个求交，结果相同
+            rec.light = None;
+            assert_eq!(lights.pdf(&ray, &rec), pdf);
+        }
+        assert!(light_hits > 0);
+    }
+
+    #[test]
+    fn directional_disk_samples_match_escaped_pdf() {
+        seed_rng(9);
+        for angular_diameter in [0.53, 30.0] {
+            let light = AnalyticLight::directional(
+                Vec3(0.3, -1.0, 0.2),
+                Vec3(2.0, 2.0, 2.0),
+                angular_diameter,
+            );
+            assert!(!light.is_delta());
+            for _ in 0..1000 {
+                let sample = light.sample(Vec3(0.0, 0.0, 0.0)).unwrap();
+                let (radiance, pdf) = light.escaped(sample.direction).unwrap();
+                assert_eq!(radiance.0, sample.radiance.0);
+                assert!((pdf - sample.pdf).abs() <= 1e-4 * pdf);
+            }
+        }
+        // 用均匀采样估计 ∫ pdf = 1，以及垂直入射时的照度
+        let light = AnalyticLight::directional(Vec3(0.0, -1.0, 0.0), Vec3(2.0, 2.0, 2.0), 30.0);
+        let count = 400_000;
+        let mut integral = 0.0f64;
+        let mut irradiance = 0.0f64;
+        for _ in 0..count {
+            let direction = Vec3::random_unit_vector();
+            if let Some((radiance, pdf)) = light.escaped(direction) {
+                integral += (4.0 * PI * pdf) as f64;
+                irradiance += (4.0 * PI * radiance.0 * direction.y()) as f64;
+            }
+        }
+        let integral = (integral / count as f64) as f32;
+        let irradiance = (irradiance / count as f64) as f32;
+        assert!((integral - 1.0).abs() < 0.03, "∫ pdf = {}", integral);
+        assert!((irradiance - 2.0).abs() < 0.06, "irradiance {}", irradiance);
+    }
+
+    #[test]
+    fn point_lights_fall_off_with_distance_squared() {
+        let light = AnalyticLight::point(Vec3(1.0, 2.0, 3.0), Vec3(8.0, 4.0, 2.0));
+        for distance in [0.5, 1.0, 2.0, 10.0] {
+            let origin = Vec3(1.0, 2.0, 3.0) - distance * Vec3(0.0, 0.6, 0.8);
+            let sample = light.sample(origin).unwrap();
+            assert!(sample.delta);
+            assert_eq!(sample.pdf, 1.0);
+            assert!((sample.distance - distance).abs() < 1e-5);
+            assert!((sample.direction - Vec3(0.0, 0.6, 0.8)).length() < 1e-5);
+            let expected = Vec3(8.0, 4.0, 2.0) / (distance * distance);
+            assert!((sample.radiance - expected).length() < 1e-4 * expected.length());
+        }
+        // 在光源所在的位置上没有确定的方向
+        assert!(light.sample(Vec3(1.0, 2.0, 3.0)).is_none());
+    }
+
+    #[test]
+    fn spot_lights_fade_between_the_falloff_and_cone_angles() {
+        // 半角 30 度，最外面 10 度逐渐减弱
+        let light = AnalyticLight::spot(
+            Vec3(0.0, 5.0, 0.0),
+            Vec3(0.0, -2.0, 0.0),
+            Vec3(10.0, 10.0, 10.0),
+            30.0,
+            10.0,
+        );
+        let at = |degrees: f32| {
+            let angle = degrees_to_radians(degrees);
+            let origin = Vec3(5.0 * libm::tanf(angle), 0.0, 0.0);
+            let full = 10.0 / (origin - Vec3(0.0, 5.0, 0.0)).length_squared();
+            (light.sample(origin), full)
+        };
+
+        // 内锥里是全部强度，和点光源一样随距离衰减
+        for degrees in [0.0, 10.0, 19.0] {
+            let (sample, full) = at(degrees);
+            let sample = sample.unwrap();
+            assert!(sample.delta);
+            assert!(
+                (sample.radiance.0 - full).abs() < 1e-5 * full,
+                "{}",
+                degrees
+            );
+        }
+
+        // 过渡带里按 smoothstep 减弱
+        let (sample, full) = at(25.0);
+        let cos = |degrees: f32| libm::cosf(degrees_to_radians(degrees));
+        let t = (cos(25.0) - cos(30.0)) / (cos(20.0) - cos(30.0));
+        let expected = full * t * t * (3.0 - 2.0 * t);
+        let radiance = sample.unwrap().radiance.0;
+        assert!(radiance > 0.0 && radiance < full);
+        assert!(
+            (radiance - expected).abs() < 1e-4 * full,
+            "{} {}",
+            radiance,
+            expected
+        );
+
+        // 光锥外面和光源背后都照不到
+        assert!(at(35.0).0.is_none());
+        assert!(light.sample(Vec3(0.0, 10.0, 0.0)).is_none());
+    }
+}
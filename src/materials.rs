@@ -1,46 +1,90 @@
 use crate::hit::Hit;
+use crate::microfacet::{self, fresnel_conductor, fresnel_dielectric, Frame, Ggx};
 use crate::ray::Ray;
+use crate::texture::{SolidColor, Texture};
+use crate::utils::random_f32;
 use crate::vec::Vec3;
-use rand::random;
+use std::f32::consts::PI;
+use std::sync::Arc;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Scatter {
+    // 不是 delta 分布时等于 eval / pdf
     pub attenuation: Vec3,
     pub ray: Ray,
+    // 方向来自 delta 分布（理想镜面反射或折射），不能用 eval 和 pdf 计算
+    pub delta: bool,
 }
 
 // 材料将告诉我们光线如何与表面相互作用
+// 下面的 wi 是散射（指向光源）的方向，wo 是指向观察者的方向，都是从击中点出发的单位向量
 pub trait Material: Send + Sync {
     fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter>;
+
+    // BSDF 乘以 |cos θi|，参与介质中是相函数的值
+    // delta 分布返回 0
+    fn eval(&self, _rec: &Hit, _wi: Vec3, _wo: Vec3) -> Vec3 {
+        Vec3(0.0, 0.0, 0.0)
+    }
+
+    // 给定 wo 时 scatter 采样到 wi 的概率密度（对立体角），delta 分布返回 0
+    fn pdf(&self, _rec: &Hit, _wi: Vec3, _wo: Vec3) -> f32 {
+        0.0
+    }
+
+    // 自发光，不发光的材料返回黑色
+    fn emitted(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+        Vec3(0.0, 0.0, 0.0)
+    }
+
+    // 发光的材料返回 true，使用它的物体会加入光源列表
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
-#[derive(Debug)]
 pub struct Lambertian {
     // 反射率
-    pub albedo: Vec3,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(color: Vec3) -> Lambertian {
-        Lambertian { albedo: color }
+        Lambertian::textured(Arc::new(SolidColor::new(color)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
         let normal = rec.normal.unwrap();
         let mut scatter_direction = normal + Vec3::random_unit_vector();
         if scatter_direction.near_zero() {
             scatter_direction = normal;
         }
         Some(Scatter {
-            attenuation: self.albedo,
-            ray: Ray {
-                origin: rec.p,
-                direction: scatter_direction,
-            },
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            ray: Ray::with_time(rec.p, scatter_direction, r_in.time),
+            delta: false,
         })
     }
+
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        self.pdf(rec, wi, wo) * self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    // 余弦分布：cos(theta) / π
+    fn pdf(&self, rec: &Hit, wi: Vec3, _wo: Vec3) -> f32 {
+        let cosine = rec.normal.unwrap().dot(wi);
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -62,20 +106,46 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
         let reflected = Vec3::reflect(&r_in.direction.to_unit_vector(), &rec.normal.unwrap());
-        let scattered = Ray {
-            origin: rec.p,
-            direction: reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-        };
+        let scattered = Ray::with_time(
+            rec.p,
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            r_in.time,
+        );
         let same_direction = scattered.direction.dot(rec.normal.unwrap()) > 0.0;
         if same_direction {
             Some(Scatter {
                 attenuation: self.albedo,
                 ray: scattered,
+                delta: self.fuzz == 0.0,
             })
         } else {
             None
         }
     }
+
+    // 采样到的方向被接受时权重都是 albedo，所以 eval = albedo * pdf
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        self.pdf(rec, wi, wo) * self.albedo
+    }
+
+    // 散射方向是以反射方向为球心、半径为 fuzz 的球内均匀分布的点的方向，
+    // 概率密度等于沿这个方向穿过球的那段 ∫ t² dt 除以球的体积
+    fn pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+        let normal = rec.normal.unwrap();
+        if self.fuzz == 0.0 || wi.dot(normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = Vec3::reflect(&-wo, &normal);
+        let b = wi.dot(reflected);
+        let discriminant = b * b - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t1 = (b - discriminant.sqrt()).max(0.0);
+        let t2 = (b + discriminant.sqrt()).max(0.0);
+        let volume = 4.0 / 3.0 * PI * self.fuzz * self.fuzz * self.fuzz;
+        (t2 * t2 * t2 - t1 * t1 * t1) / (3.0 * volume)
+    }
 }
 
 /// 绝缘体
@@ -109,22 +179,568 @@ impl Material for Dielectric {
         let cos_theta = libm::fminf((-unit_direction).dot(rec.normal.unwrap()), 1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction;
-        if cannot_refract || (schlick(cos_theta, refraction_ratio) > random::<f32>()) {
-            direction = Vec3::reflect(&unit_direction, &rec.normal.unwrap());
+        let direction = if cannot_refract || (schlick(cos_theta, refraction_ratio) > random_f32()) {
+            Vec3::reflect(&unit_direction, &rec.normal.unwrap())
         } else {
-            direction = Vec3::other_refract(unit_direction, rec.normal.unwrap(), refraction_ratio);
-        }
+            Vec3::other_refract(unit_direction, rec.normal.unwrap(), refraction_ratio)
+        };
 
         // let refracted = Vec3::refract(&unit_direction, &rec.normal.unwrap(), refraction_ratio);
         Some(Scatter {
             attenuation,
-            ray: Ray {
-                origin: rec.p,
-                direction,
+            ray: Ray::with_time(rec.p, direction, r_in.time),
+            delta: true,
+        })
+    }
+}
+
+/// 常见金属在 RGB 三个波长 (约 650、550、450 nm) 上的复折射率 (eta, k)
+pub fn conductor_ior(name: &str) -> Option<(Vec3, Vec3)> {
+    Some(match name {
+        "gold" => (Vec3(0.143, 0.374, 1.442), Vec3(3.983, 2.386, 1.603)),
+        "silver" => (Vec3(0.155, 0.117, 0.138), Vec3(4.828, 3.122, 2.147)),
+        "copper" => (Vec3(0.200, 0.924, 1.102), Vec3(3.912, 2.452, 2.142)),
+        "aluminium" => (Vec3(1.657, 0.880, 0.521), Vec3(9.224, 6.270, 4.837)),
+        _ => return None,
+    })
+}
+
+/// 粗糙导体，GGX 微表面模型，菲涅尔项使用复折射率 eta + i k
+/// `alpha` 是 GGX 分布的宽度，为 0 时是理想镜面
+#[derive(Debug)]
+pub struct RoughConductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+    pub alpha: f32,
+}
+
+impl RoughConductor {
+    pub fn new(eta: Vec3, k: Vec3, alpha: f32) -> RoughConductor {
+        RoughConductor { eta, k, alpha }
+    }
+}
+
+impl Material for RoughConductor {
+    // 按可见法线分布采样微表面，权重为 F * G2 / G1
+    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let wo = frame.to_local(-r_in.direction.to_unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let ggx = Ggx::new(self.alpha);
+        let m = ggx.sample_visible_normal(wo, random_f32(), random_f32());
+        let wi = microfacet::reflect(wo, m);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let fresnel = fresnel_conductor(wo.dot(m), self.eta, self.k);
+        Some(Scatter {
+            attenuation: (ggx.g2(wo, wi) / ggx.g1(wo)) * fresnel,
+            ray: Ray::with_time(rec.p, frame.to_world(wi), r_in.time),
+            delta: ggx.is_smooth(),
+        })
+    }
+
+    // F D G2 / (4 cos θo)，cos θi 已经约掉
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        let ggx = Ggx::new(self.alpha);
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if ggx.is_smooth() || wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        let h = (wi + wo).to_unit_vector();
+        let fresnel = fresnel_conductor(wo.dot(h), self.eta, self.k);
+        (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z())) * fresnel
+    }
+
+    fn pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+        let ggx = Ggx::new(self.alpha);
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if ggx.is_smooth() || wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return 0.0;
+        }
+        ggx.reflection_pdf(wo, wi)
+    }
+}
+
+/// 粗糙绝缘体，GGX 微表面上的反射和折射 (Walter 等人 2007)
+/// 每次按微表面法线上的菲涅尔反射率随机选择反射或者折射
+#[derive(Debug)]
+pub struct RoughDielectric {
+    pub ir: f32,
+    pub alpha: f32,
+}
+
+impl RoughDielectric {
+    pub fn new(index_of_refraction: f32, alpha: f32) -> RoughDielectric {
+        RoughDielectric {
+            ir: index_of_refraction,
+            alpha,
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let wo = frame.to_local(-r_in.direction.to_unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        // 透射一侧与入射一侧的折射率之比
+        let eta = if rec.front_face.unwrap() {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        let ggx = Ggx::new(self.alpha);
+        let m = ggx.sample_visible_normal(wo, random_f32(), random_f32());
+        let fresnel = fresnel_dielectric(wo.dot(m), eta);
+        let wi = match microfacet::refract(wo, m, eta) {
+            Some(refracted) if random_f32() >= fresnel => {
+                if refracted.z() >= 0.0 {
+                    return None;
+                }
+                refracted
+            }
+            _ => {
+                let reflected = microfacet::reflect(wo, m);
+                if reflected.z() <= 0.0 {
+                    return None;
+                }
+                reflected
+            }
+        };
+        // 选择的概率和菲涅尔项抵消，只剩下遮蔽项
+        let weight = ggx.g2(wo, wi) / ggx.g1(wo);
+        Some(Scatter {
+            attenuation: Vec3(weight, weight, weight),
+            ray: Ray::with_time(rec.p, frame.to_world(wi), r_in.time),
+            delta: ggx.is_smooth(),
+        })
+    }
+
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        let value = self.eval_pdf(rec, wi, wo).0;
+        Vec3(value, value, value)
+    }
+
+    fn pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+        self.eval_pdf(rec, wi, wo).1
+    }
+}
+
+impl RoughDielectric {
+    // 同时计算 eval 和 pdf，两者共用微表面法线和菲涅尔项
+    fn eval_pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> (f32, f32) {
+        let ggx = Ggx::new(self.alpha);
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (0.0, 0.0);
+        }
+        let eta = if rec.front_face.unwrap() {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        if wi.z() > 0.0 {
+            let h = (wi + wo).to_unit_vector();
+            let fresnel = fresnel_dielectric(wo.dot(h), eta);
+            let value = fresnel * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z());
+            (value, fresnel * ggx.reflection_pdf(wo, wi))
+        } else {
+            let m = match microfacet::transmission_normal(wo, wi, eta) {
+                Some(m) => m,
+                None => return (0.0, 0.0),
+            };
+            let (cos_om, cos_im) = (wo.dot(m), wi.dot(m));
+            let denom = cos_im + cos_om / eta;
+            let transmitted = 1.0 - fresnel_dielectric(cos_om, eta);
+            let value = transmitted * ggx.d(m) * ggx.g2(wo, wi) * cos_om * cos_im.abs()
+                / (wo.z() * denom * denom);
+            let pdf = transmitted * ggx.visible_pdf(wo, m) * cos_im.abs() / (denom * denom);
+            (value, pdf)
+        }
+    }
+}
+
+/// Disney 风格的 principled 材质，所有参数都可以来自纹理
+/// 标量参数取纹理颜色的亮度。由漫反射、光泽、GGX 反射、GGX 透射和清漆几层组成，
+/// 采样时按各层的权重随机选一层，再用所有层的概率密度之和计算权重。
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // 绝缘体正入射时的反射率，0.5 对应 4%
+    pub specular: Arc<dyn Texture>,
+    // 绝缘体的反射带上多少基础颜色的色调
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+}
+
+// 在击中点取出的参数
+struct PrincipledParams {
+    base_color: Vec3,
+    metallic: f32,
+    // 反射层正入射时的反射率
+    specular0: Vec3,
+    sheen: Vec3,
+    clearcoat: f32,
+    transmission: f32,
+    // 透射一侧与入射一侧的折射率之比
+    eta: f32,
+    ggx: Ggx,
+}
+
+// 清漆层固定使用很光滑的 GGX 分布
+const CLEARCOAT_ALPHA: f32 = 0.05;
+
+impl Principled {
+    /// 默认是粗糙度 0.5 的非金属，折射率 1.5
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        let constant = |v: f32| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Vec3(v, v, v))) };
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Principled {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Principled {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>) -> Principled {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_specular_tint(mut self, specular_tint: Arc<dyn Texture>) -> Principled {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>) -> Principled {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Arc<dyn Texture>) -> Principled {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: Arc<dyn Texture>) -> Principled {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn with_ior(mut self, ior: Arc<dyn Texture>) -> Principled {
+        self.ior = ior;
+        self
+    }
+
+    fn params(&self, rec: &Hit) -> PrincipledParams {
+        let color = |texture: &Arc<dyn Texture>| texture.value(rec.u, rec.v, rec.p);
+        let scalar = |texture: &Arc<dyn Texture>| color(texture).luminance();
+        let base_color = color(&self.base_color);
+        let metallic = scalar(&self.metallic).clamp(0.0, 1.0);
+        let roughness = scalar(&self.roughness).clamp(0.0, 1.0);
+        // 去掉亮度只保留色调
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            Vec3(1.0, 1.0, 1.0)
+        };
+        let white = Vec3(1.0, 1.0, 1.0);
+        let mix = |a: Vec3, b: Vec3, t: f32| (1.0 - t) * a + t * b;
+        let specular_tint = mix(white, tint, scalar(&self.specular_tint).clamp(0.0, 1.0));
+        let specular0 = mix(
+            0.08 * scalar(&self.specular).max(0.0) * specular_tint,
+            base_color,
+            metallic,
+        );
+        let ior = scalar(&self.ior).max(1e-3);
+        PrincipledParams {
+            base_color,
+            metallic,
+            specular0,
+            sheen: scalar(&self.sheen).max(0.0) * mix(white, tint, 0.5),
+            clearcoat: scalar(&self.clearcoat).max(0.0),
+            transmission: scalar(&self.transmission).clamp(0.0, 1.0),
+            eta: if rec.front_face.unwrap() {
+                ior
+            } else {
+                1.0 / ior
             },
+            // 感知上的粗糙度平方后作为 GGX 的 alpha
+            ggx: Ggx::new((roughness * roughness).max(microfacet::MIN_ALPHA)),
+        }
+    }
+}
+
+impl PrincipledParams {
+    fn diffuse_weight(&self) -> f32 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f32 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    // 按各层大致的能量选择采样哪一层：漫反射、反射、透射、清漆
+    fn lobe_probabilities(&self, wo: Vec3) -> [f32; 4] {
+        let specular = schlick_color(self.specular0, wo.z()).luminance();
+        let weights = [
+            self.diffuse_weight() * self.base_color.luminance().max(0.01),
+            specular.max(0.01),
+            self.transmission_weight(),
+            0.25 * self.clearcoat * schlick(wo.z(), 1.5),
+        ];
+        let total: f32 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    /// BSDF 的值，方向都在局部坐标系中，`wo` 在上半球
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        if cos_i > 0.0 {
+            let h = (wo + wi).to_unit_vector();
+            let cos_d = wi.dot(h);
+            let fresnel_weight = (1.0 - cos_d).clamp(0.0, 1.0).powi(5);
+            let mut f = Vec3(0.0, 0.0, 0.0);
+
+            let diffuse_weight = self.diffuse_weight();
+            if diffuse_weight > 0.0 {
+                // 粗糙表面在掠射角的逆反射
+                let roughness = self.ggx.alpha.sqrt();
+                let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+                let fd = |cos: f32| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+                let diffuse = (fd(cos_o) * fd(cos_i) / PI) * self.base_color;
+                f = f + diffuse_weight * (diffuse + fresnel_weight * self.sheen);
+            }
+
+            let g = self.ggx.g2(wo, wi);
+            let specular = schlick_color(self.specular0, cos_d);
+            f = f + (self.ggx.d(h) * g / (4.0 * cos_o * cos_i)) * specular;
+
+            if self.clearcoat > 0.0 {
+                let coat = Ggx::new(CLEARCOAT_ALPHA);
+                let fc = schlick(cos_d, 1.5);
+                let value = 0.25 * self.clearcoat * fc * coat.d(h) * coat.g2(wo, wi)
+                    / (4.0 * cos_o * cos_i);
+                f = f + Vec3(value, value, value);
+            }
+            f
+        } else if cos_i < 0.0 && self.transmission_weight() > 0.0 {
+            let m = match microfacet::transmission_normal(wo, wi, self.eta) {
+                Some(m) => m,
+                None => return Vec3(0.0, 0.0, 0.0),
+            };
+            let (cos_om, cos_im) = (wo.dot(m), wi.dot(m));
+            let denom = cos_im + cos_om / self.eta;
+            let fresnel = fresnel_dielectric(cos_om, self.eta);
+            let value =
+                (1.0 - fresnel) * self.ggx.d(m) * self.ggx.g2(wo, wi) * cos_om.abs() * cos_im.abs()
+                    / (cos_o * cos_i.abs() * denom * denom);
+            // 透射的颜色用基础颜色的平方根，进出各一次之后正好是基础颜色
+            let color = Vec3(
+                self.base_color.0.max(0.0).sqrt(),
+                self.base_color.1.max(0.0).sqrt(),
+                self.base_color.2.max(0.0).sqrt(),
+            );
+            (self.transmission_weight() * value) * color
+        } else {
+            Vec3(0.0, 0.0, 0.0)
+        }
+    }
+
+    /// 按 `lobe_probabilities` 混合各层采样方法得到的概率密度
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let [p_diffuse, p_specular, p_transmission, p_clearcoat] = self.lobe_probabilities(wo);
+        if wi.z() > 0.0 {
+            p_diffuse * wi.z() / PI
+                + p_specular * self.ggx.reflection_pdf(wo, wi)
+                + p_clearcoat * Ggx::new(CLEARCOAT_ALPHA).reflection_pdf(wo, wi)
+        } else if wi.z() < 0.0 && p_transmission > 0.0 {
+            let m = match microfacet::transmission_normal(wo, wi, self.eta) {
+                Some(m) => m,
+                None => return 0.0,
+            };
+            let denom = wi.dot(m) + wo.dot(m) / self.eta;
+            p_transmission * self.ggx.visible_pdf(wo, m) * wi.dot(m).abs() / (denom * denom)
+        } else {
+            0.0
+        }
+    }
+
+    fn sample(&self, wo: Vec3) -> Option<Vec3> {
+        let [p_diffuse, p_specular, p_transmission, _] = self.lobe_probabilities(wo);
+        let u = random_f32();
+        let (wi, transmitted) = if u < p_diffuse {
+            (cosine_direction(), false)
+        } else if u < p_diffuse + p_specular {
+            let m = self
+                .ggx
+                .sample_visible_normal(wo, random_f32(), random_f32());
+            (microfacet::reflect(wo, m), false)
+        } else if u < p_diffuse + p_specular + p_transmission {
+            let m = self
+                .ggx
+                .sample_visible_normal(wo, random_f32(), random_f32());
+            (microfacet::refract(wo, m, self.eta)?, true)
+        } else {
+            let coat = Ggx::new(CLEARCOAT_ALPHA);
+            let m = coat.sample_visible_normal(wo, random_f32(), random_f32());
+            (microfacet::reflect(wo, m), false)
+        };
+        // 落到另一侧的方向不计入概率密度，当作被吸收
+        if transmitted == (wi.z() < 0.0) {
+            Some(wi)
+        } else {
+            None
+        }
+    }
+}
+
+// 余弦分布的上半球方向
+fn cosine_direction() -> Vec3 {
+    let r1 = random_f32();
+    let r2 = random_f32();
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    Vec3(
+        r * libm::cosf(phi),
+        r * libm::sinf(phi),
+        (1.0 - r2).max(0.0).sqrt(),
+    )
+}
+
+fn schlick_color(f0: Vec3, cosine: f32) -> Vec3 {
+    let weight = (1.0 - cosine).clamp(0.0, 1.0).powi(5);
+    f0 + weight * (Vec3(1.0, 1.0, 1.0) - f0)
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+        let params = self.params(rec);
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let wo = frame.to_local(-r_in.direction.to_unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let wi = params.sample(wo)?;
+        let pdf = params.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = params.eval(wo, wi);
+        Some(Scatter {
+            attenuation: (wi.z().abs() / pdf) * f,
+            ray: Ray::with_time(rec.p, frame.to_world(wi), r_in.time),
+            delta: false,
         })
     }
+
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if wo.z() <= 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        wi.z().abs() * self.params(rec).eval(wo, wi)
+    }
+
+    fn pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+        let frame = Frame::from_normal(rec.normal.unwrap());
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.params(rec).pdf(wo, wi)
+    }
+}
+
+/// 漫射光源
+/// 向各个方向均匀发光，不反射光线
+#[derive(Debug)]
+pub struct DiffuseLight {
+    pub emit: Vec3,
+}
+
+impl DiffuseLight {
+    pub fn new(color: Vec3) -> DiffuseLight {
+        DiffuseLight { emit: color }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &Hit) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+        self.emit
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emit.0 > 0.0 || self.emit.1 > 0.0 || self.emit.2 > 0.0
+    }
+}
+
+/// 各向同性的相函数，用于参与介质
+/// 散射方向在球面上均匀分布
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(color: Vec3) -> Isotropic {
+        Isotropic::textured(Arc::new(SolidColor::new(color)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+        Some(Scatter {
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            ray: Ray::with_time(rec.p, Vec3::random_unit_vector(), r_in.time),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        self.pdf(rec, wi, wo) * self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    // 均匀分布：1 / 4π
+    fn pdf(&self, _rec: &Hit, _wi: Vec3, _wo: Vec3) -> f32 {
+        1.0 / (4.0 * PI)
+    }
 }
 
 /// Christophe Schlick's approximation for the reflectivity of glass,
@@ -134,3 +750,108 @@ fn schlick(cosine: f32, index: f32) -> f32 {
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * ((1.0 - cosine).powi(5))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::seed_rng;
+
+    fn hit(front_face: bool) -> Hit {
+        let mut rec = Hit::new(1.0, Vec3(0.0, 0.0, 0.0));
+        rec.normal = Some(Vec3(0.0, 1.0, 0.0));
+        rec.front_face = Some(front_face);
+        rec
+    }
+
+    fn constant(v: f32) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new(Vec3(v, v, v)))
+    }
+
+    fn materials() -> Vec<(&'static str, Box<dyn Material>)> {
+        vec![
+            ("lambertian", Box::new(Lambertian::new(Vec3(0.8, 0.5, 0.2)))),
+            ("metal", Box::new(Metal::new(Vec3(0.9, 0.9, 0.9), 0.4))),
+            (
+                "rough_conductor",
+                Box::new(RoughConductor::new(
+                    Vec3(0.2, 0.9, 1.1),
+                    Vec3(3.9, 2.4, 2.1),
+                    0.5,
+                )),
+            ),
+            ("rough_dielectric", Box::new(RoughDielectric::new(1.5, 0.5))),
+            (
+                "principled",
+                Box::new(
+                    Principled::new(constant(0.6))
+                        .with_metallic(constant(0.3))
+                        .with_transmission(constant(0.5))
+                        .with_clearcoat(constant(1.0))
+                        .with_sheen(constant(0.5)),
+                ),
+            ),
+            ("isotropic", Box::new(Isotropic::new(Vec3(0.5, 0.5, 0.5)))),
+        ]
+    }
+
+    #[test]
+    fn sampled_weight_matches_eval_over_pdf() {
+        seed_rng(5);
+        let ray = Ray::new(Vec3(-0.6, 0.8, 0.0), Vec3(0.6, -0.8, 0.0));
+        let wo = -ray.direction.to_unit_vector();
+        for front_face in [true, false] {
+            let rec = hit(front_face);
+            for (name, material) in materials() {
+                for _ in 0..2_000 {
+                    let scatter = match material.scatter(&ray, &rec) {
+                        Some(scatter) => scatter,
+                        None => continue,
+                    };
+                    assert!(!scatter.delta, "{}", name);
+                    let wi = scatter.ray.direction.to_unit_vector();
+                    let pdf = material.pdf(&rec, wi, wo);
+                    assert!(pdf > 0.0, "{}: pdf of a sampled direction is 0", name);
+                    let expected = material.eval(&rec, wi, wo) / pdf;
+                    let error = (scatter.attenuation - expected).length();
+                    assert!(
+                        error <= 1e-3 * expected.length().max(1.0),
+                        "{}: attenuation {:?} eval / pdf {:?}",
+                        name,
+                        scatter.attenuation,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_the_scattered_fraction() {
+        // 在整个球面上积分 pdf，等于 scatter 返回方向（没有被吸收）的比例
+        seed_rng(6);
+        let ray = Ray::new(Vec3(-0.6, 0.8, 0.0), Vec3(0.6, -0.8, 0.0));
+        let wo = -ray.direction.to_unit_vector();
+        let rec = hit(true);
+        let count = 200_000;
+        for (name, material) in materials() {
+            let mut integral = 0.0f64;
+            let mut scattered = 0;
+            for _ in 0..count {
+                let wi = Vec3::random_unit_vector();
+                integral += (4.0 * PI * material.pdf(&rec, wi, wo)) as f64;
+                if material.scatter(&ray, &rec).is_some() {
+                    scattered += 1;
+                }
+            }
+            let integral = (integral / count as f64) as f32;
+            let fraction = scattered as f32 / count as f32;
+            assert!(
+                (integral - fraction).abs() < 0.03,
+                "{}: ∫ pdf = {} but {} of the samples scattered",
+                name,
+                integral,
+                fraction
+            );
+        }
+    }
+}
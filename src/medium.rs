@@ -0,0 +1,464 @@
+use crate::aabb::Aabb;
+use crate::hit::{Hit, Hittable};
+use crate::materials::{Isotropic, Material, Scatter};
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::utils::random_f32;
+use crate::vec::Vec3;
+use std::sync::Arc;
+
+/// 密度均匀的参与介质，例如雾和烟
+/// 边界必须是封闭的凸物体，光线在内部每走一段距离都有一定概率发生散射
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f32,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, density: f32, albedo: Vec3) -> ConstantMedium {
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn textured(
+        boundary: Box<dyn Hittable>,
+        density: f32,
+        albedo: Arc<dyn Texture>,
+    ) -> ConstantMedium {
+        ConstantMedium::with_phase_function(
+            boundary,
+            density,
+            Arc::new(Isotropic::textured(albedo)),
+        )
+    }
+
+    pub fn with_phase_function(
+        boundary: Box<dyn Hittable>,
+        density: f32,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+/// 光线在边界内部的一段 [t1, t2]，已经截断到 [t_min, t_max]
+fn inside(boundary: &dyn Hittable, t_min: f32, t_max: f32, ray: &Ray) -> Option<(f32, f32)> {
+    // 找到光线进入和离开边界的位置，起点在介质内部时进入点在身后
+    let rec1 = boundary.hit(f32::MIN, f32::MAX, ray)?;
+    let rec2 = boundary.hit(rec1.t + 0.0001, f32::MAX, ray)?;
+    let t1 = rec1.t.max(t_min).max(0.0);
+    let t2 = rec2.t.min(t_max);
+    if t1 >= t2 {
+        None
+    } else {
+        Some((t1, t2))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let (t1, t2) = inside(self.boundary.as_ref(), t_min, t_max, ray)?;
+        let ray_length = ray.direction.length();
+        let distance_inside_boundary = (t2 - t1) * ray_length;
+        // 按指数分布采样自由程
+        let hit_distance = self.neg_inv_density * libm::logf(random_f32());
+        if hit_distance > distance_inside_boundary {
+            return None;
+        }
+
+        let t = t1 + hit_distance / ray_length;
+        let mut hit = Hit::new(t, ray.at(t));
+        // 介质中没有表面，法线和朝向随便取
+        hit.normal = Some(Vec3(1.0, 0.0, 0.0));
+        hit.front_face = Some(true);
+        hit.material = Some(self.phase_function.clone());
+        Some(hit)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.boundary.aabb()
+    }
+
+    // 均匀介质的透射率有解析解 exp(-σ d)
+    fn transmittance(&self, t_min: f32, t_max: f32, ray: &Ray) -> f32 {
+        match inside(self.boundary.as_ref(), t_min, t_max, ray) {
+            Some((t1, t2)) => {
+                let distance = (t2 - t1) * ray.direction.length();
+                libm::expf(distance / self.neg_inv_density)
+            }
+            None => 1.0,
+        }
+    }
+}
+
+/// 空间中变化的密度
+pub trait DensityField: Send + Sync {
+    fn density(&self, p: Vec3) -> f32;
+    // 密度的上界，用作追踪时的主导密度 (majorant)
+    fn max_density(&self) -> f32;
+}
+
+/// 铺满包围盒 `bounds` 的三维网格，格点之间三线性插值，盒子外为 0
+/// `values` 按 x、y、z 的顺序存放，x 变化最快
+pub struct GridDensity {
+    pub bounds: Aabb,
+    pub resolution: [usize; 3],
+    values: Vec<f32>,
+    max: f32,
+}
+
+impl GridDensity {
+    /// `resolution` 中有 0、`values` 的个数和格点数不同，或者有负数、非有限值时 panic
+    pub fn new(bounds: Aabb, resolution: [usize; 3], values: Vec<f32>) -> GridDensity {
+        assert!(resolution.iter().all(|&n| n >= 1));
+        assert_eq!(values.len(), resolution[0] * resolution[1] * resolution[2]);
+        assert!(values.iter().all(|&v| v >= 0.0 && v.is_finite()));
+        let max = values.iter().copied().fold(0.0, f32::max);
+        GridDensity {
+            bounds,
+            resolution,
+            values,
+            max,
+        }
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(z * ny + y) * nx + x]
+    }
+}
+
+impl DensityField for GridDensity {
+    fn density(&self, p: Vec3) -> f32 {
+        let extent = self.bounds.extent();
+        let mut cell = [0usize; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let t = (p[axis] - self.bounds.min[axis]) / extent[axis];
+            if !(0.0..=1.0).contains(&t) {
+                return 0.0;
+            }
+            // 格点位于每个体素的中心
+            let n = self.resolution[axis];
+            let x = (t * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            let i = (x as usize).min(n.saturating_sub(2));
+            cell[axis] = i;
+            frac[axis] = if n > 1 { x - i as f32 } else { 0.0 };
+        }
+        let next = |axis: usize| (cell[axis] + 1).min(self.resolution[axis] - 1);
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let pick = |axis: usize| corner & (1 << axis) != 0;
+            let mut weight = 1.0;
+            let mut index = [0usize; 3];
+            for axis in 0..3 {
+                if pick(axis) {
+                    weight *= frac[axis];
+                    index[axis] = next(axis);
+                } else {
+                    weight *= 1.0 - frac[axis];
+                    index[axis] = cell[axis];
+                }
+            }
+            result += weight * self.value(index[0], index[1], index[2]);
+        }
+        result
+    }
+
+    fn max_density(&self) -> f32 {
+        self.max
+    }
+}
+
+/// Perlin 湍流生成的密度场，可以做云和爆炸
+/// 湍流值低于 `threshold` 的地方为空，其余部分重新映射到 [0, density]
+pub struct NoiseDensity {
+    noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+    pub threshold: f32,
+    pub density: f32,
+}
+
+impl NoiseDensity {
+    /// `threshold` 不在 [0, 1) 中时 panic
+    pub fn new(seed: u64, scale: f32, depth: usize, threshold: f32, density: f32) -> NoiseDensity {
+        assert!((0.0..1.0).contains(&threshold));
+        NoiseDensity {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+            threshold,
+            density,
+        }
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: Vec3) -> f32 {
+        let turbulence = self.noise.turbulence(self.scale * p, self.depth).min(1.0);
+        let t = ((turbulence - self.threshold) / (1.0 - self.threshold)).max(0.0);
+        self.density * t
+    }
+
+    fn max_density(&self) -> f32 {
+        self.density
+    }
+}
+
+/// 吸收光线的碰撞，路径在这里结束
+struct Absorb;
+
+impl Material for Absorb {
+    fn scatter(&self, _r_in: &Ray, _rec: &Hit) -> Option<Scatter> {
+        None
+    }
+}
+
+/// 密度随位置变化的参与介质
+/// 吸收系数 `sigma_a` 和散射系数 `sigma_s` 都乘以密度场的值。
+/// 碰撞位置用 delta tracking 采样，阴影光线的透射率用 ratio tracking 估计，两者都是无偏的。
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable>,
+    density: Arc<dyn DensityField>,
+    pub sigma_a: f32,
+    pub sigma_s: f32,
+    // 主导消光系数，不小于介质中任意一点的 (sigma_a + sigma_s) * density
+    majorant: f32,
+    phase_function: Arc<dyn Material>,
+    absorb: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    /// `sigma_a` 或 `sigma_s` 为负数时 panic
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: Arc<dyn DensityField>,
+        sigma_a: f32,
+        sigma_s: f32,
+        phase_function: Arc<dyn Material>,
+    ) -> HeterogeneousMedium {
+        assert!(sigma_a >= 0.0 && sigma_s >= 0.0);
+        let majorant = (sigma_a + sigma_s) * density.max_density();
+        HeterogeneousMedium {
+            boundary,
+            density,
+            sigma_a,
+            sigma_s,
+            majorant,
+            phase_function,
+            absorb: Arc::new(Absorb),
+        }
+    }
+
+    /// 以主导消光系数为参数的指数分布采样下一个候选碰撞点
+    fn step(&self, t: f32, ray_length: f32) -> f32 {
+        t - libm::logf(1.0 - random_f32()) / (self.majorant * ray_length)
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (t1, t2) = inside(self.boundary.as_ref(), t_min, t_max, ray)?;
+        let ray_length = ray.direction.length();
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let mut t = t1;
+        loop {
+            t = self.step(t, ray_length);
+            if t >= t2 {
+                return None;
+            }
+            let p = ray.at(t);
+            // 以 σt(p) / 主导系数 的概率接受为真实碰撞，否则是虚拟碰撞，继续前进
+            let xi = random_f32() * self.majorant;
+            let density = self.density.density(p);
+            if xi < sigma_t * density {
+                let mut hit = Hit::new(t, p);
+                hit.normal = Some(Vec3(1.0, 0.0, 0.0));
+                hit.front_face = Some(true);
+                // 真实碰撞中按 σa : σs 的比例选择吸收或散射
+                let material = if xi < self.sigma_s * density {
+                    &self.phase_function
+                } else {
+                    &self.absorb
+                };
+                hit.material = Some(material.clone());
+                return Some(hit);
+            }
+        }
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.boundary.aabb()
+    }
+
+    fn transmittance(&self, t_min: f32, t_max: f32, ray: &Ray) -> f32 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        let (t1, t2) = match inside(self.boundary.as_ref(), t_min, t_max, ray) {
+            Some(range) => range,
+            None => return 1.0,
+        };
+        let ray_length = ray.direction.length();
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let mut transmittance = 1.0;
+        let mut t = t1;
+        loop {
+            t = self.step(t, ray_length);
+            if t >= t2 {
+                return transmittance;
+            }
+            transmittance *= 1.0 - sigma_t * self.density.density(ray.at(t)) / self.majorant;
+            // 透射率很小时用俄罗斯轮盘赌提前结束
+            if transmittance < 0.1 {
+                if random_f32() < 0.5 {
+                    return 0.0;
+                }
+                transmittance *= 2.0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::Cuboid;
+    use crate::utils::seed_rng;
+
+    /// 边长为 2 的立方体，光线从 +z 方向穿过，在介质中走过的距离是 2
+    fn cube() -> Box<dyn Hittable> {
+        let material = Arc::new(Isotropic::new(Vec3(1.0, 1.0, 1.0)));
+        Box::new(Cuboid::new(
+            Vec3(-1.0, -1.0, -1.0),
+            Vec3(1.0, 1.0, 1.0),
+            material,
+        ))
+    }
+
+    /// 垂直于 z 轴、厚度为 `thickness` 的平板
+    fn slab(thickness: f32) -> Box<dyn Hittable> {
+        let material = Arc::new(Isotropic::new(Vec3(1.0, 1.0, 1.0)));
+        Box::new(Cuboid::new(
+            Vec3(-100.0, -100.0, 0.0),
+            Vec3(100.0, 100.0, thickness),
+            material,
+        ))
+    }
+
+    #[test]
+    fn constant_medium_free_paths_and_transmittance() {
+        seed_rng(11);
+        let density = 2.0;
+        let ray = Ray::new(Vec3(0.0, 0.0, -1.0), Vec3(0.0, 0.0, 0.5));
+
+        // 平板足够厚，几乎所有光线都会在里面碰撞，平均自由程是 1 / density
+        let thick = ConstantMedium::new(slab(20.0), density, Vec3(1.0, 1.0, 1.0));
+        let n = 20_000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let hit = thick.hit(0.001, f32::MAX, &ray).unwrap();
+            assert_eq!(hit.p.x(), 0.0);
+            total += hit.p.z();
+        }
+        let mean = total / n as f32;
+        assert!(
+            (mean - 1.0 / density).abs() < 0.01,
+            "mean free path {} expected {}",
+            mean,
+            1.0 / density
+        );
+
+        // 薄的平板：透射率是 exp(-σ d)，逃出介质的比例也一样
+        let thin = ConstantMedium::new(slab(0.5), density, Vec3(1.0, 1.0, 1.0));
+        let expected = libm::expf(-density * 0.5);
+        let transmittance = thin.transmittance(0.001, f32::MAX, &ray);
+        assert!(
+            (transmittance - expected).abs() < 1e-4,
+            "transmittance {} expected {}",
+            transmittance,
+            expected
+        );
+        let escaped = (0..n)
+            .filter(|_| thin.hit(0.001, f32::MAX, &ray).is_none())
+            .count();
+        let escape = escaped as f32 / n as f32;
+        assert!(
+            (escape - expected).abs() < 0.015,
+            "escape fraction {} expected {}",
+            escape,
+            expected
+        );
+        // 阴影光线只穿过介质的一部分
+        let partial = thin.transmittance(0.001, 2.5, &ray);
+        assert!((partial - libm::expf(-density * 0.25)).abs() < 1e-4);
+        // 光线错过平板时不衰减
+        let miss = Ray::new(Vec3(0.0, 0.0, -1.0), Vec3(0.0, 0.0, -1.0));
+        assert_eq!(thin.transmittance(0.001, f32::MAX, &miss), 1.0);
+    }
+
+    #[test]
+    fn constant_grid_matches_beer_lambert() {
+        seed_rng(10);
+        let (sigma_a, sigma_s) = (0.3, 0.2);
+        let density = Arc::new(GridDensity::new(
+            Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0)),
+            [2, 2, 2],
+            vec![1.0; 8],
+        ));
+        let phase_function = Arc::new(Isotropic::new(Vec3(1.0, 1.0, 1.0)));
+        let medium = HeterogeneousMedium::new(cube(), density, sigma_a, sigma_s, phase_function);
+        // 方向不是单位向量，检查距离是按光线长度换算的
+        let ray = Ray::new(Vec3(0.2, -0.3, 5.0), Vec3(0.0, 0.0, -2.0));
+        let expected = libm::expf(-(sigma_a + sigma_s) * 2.0);
+
+        let n = 20_000;
+        let transmittance = (0..n)
+            .map(|_| medium.transmittance(0.001, f32::MAX, &ray))
+            .sum::<f32>()
+            / n as f32;
+        assert!(
+            (transmittance - expected).abs() < 0.015,
+            "ratio tracking {} expected {}",
+            transmittance,
+            expected
+        );
+
+        let (mut escaped, mut scattered) = (0, 0);
+        for _ in 0..n {
+            match medium.hit(0.001, f32::MAX, &ray) {
+                None => escaped += 1,
+                Some(hit) => {
+                    assert!((2.0..=3.0).contains(&hit.t), "collision at t = {}", hit.t);
+                    let material = hit.material.as_ref().unwrap();
+                    if material.scatter(&ray, &hit).is_some() {
+                        scattered += 1;
+                    }
+                }
+            }
+        }
+        let escape = escaped as f32 / n as f32;
+        assert!(
+            (escape - expected).abs() < 0.015,
+            "delta tracking escape fraction {} expected {}",
+            escape,
+            expected
+        );
+        // 真实碰撞中散射的比例是 σs / σt
+        let albedo = scattered as f32 / (n - escaped) as f32;
+        assert!(
+            (albedo - sigma_s / (sigma_a + sigma_s)).abs() < 0.02,
+            "scattering fraction {}",
+            albedo
+        );
+    }
+}
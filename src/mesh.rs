@@ -0,0 +1,335 @@
+use crate::aabb::Aabb;
+use crate::hit::{Hit, Hittable};
+use crate::materials::Material;
+use crate::ray::Ray;
+use crate::utils::random_f32;
+use crate::vec::Vec3;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    /// 法线或 UV 的数量和顶点数不一致
+    AttributeCount {
+        attribute: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// 三角形引用了不存在的顶点
+    IndexOutOfRange {
+        triangle: usize,
+        index: usize,
+        vertices: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::AttributeCount {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "mesh has {} {} but {} vertices",
+                found, attribute, expected
+            ),
+            MeshError::IndexOutOfRange {
+                triangle,
+                index,
+                vertices,
+            } => write!(
+                f,
+                "triangle {} uses vertex {} but the mesh has {} vertices",
+                triangle, index, vertices
+            ),
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+/// 索引三角形网格
+/// 顶点、法线和 UV 存在共享的缓冲区里，每个三角形只记录三个顶点的下标。
+/// `normals` 和 `uvs` 可以为空，不为空时长度必须和 `positions` 一致，否则 `new` 返回错误。
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Result<TriangleMesh, MeshError> {
+        let vertices = positions.len();
+        for (attribute, found) in [("normals", normals.len()), ("uvs", uvs.len())] {
+            if found != 0 && found != vertices {
+                return Err(MeshError::AttributeCount {
+                    attribute,
+                    expected: vertices,
+                    found,
+                });
+            }
+        }
+        for (triangle, face) in indices.iter().enumerate() {
+            if let Some(&index) = face.iter().find(|&&i| i >= vertices) {
+                return Err(MeshError::IndexOutOfRange {
+                    triangle,
+                    index,
+                    vertices,
+                });
+            }
+        }
+        Ok(TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+        })
+    }
+
+    /// 每个三角形单独作为一个物体，交给 `BvhNode` 或 `HittableList`
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Box<dyn Hittable>> {
+        (0..mesh.indices.len())
+            .map(|index| {
+                Box::new(Triangle {
+                    mesh: mesh.clone(),
+                    index,
+                }) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+}
+
+/// 网格中的一个三角形，用 Möller–Trumbore 算法求交
+pub struct Triangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub index: usize,
+}
+
+impl Triangle {
+    fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let p = &self.mesh.positions;
+        (p[i0], p[i1], p[i2])
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let (p0, p1, p2) = self.vertices();
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let pvec = ray.direction.cross(edge2);
+        let det = edge1.dot(pvec);
+        // 光线和三角形平行
+        if libm::fabsf(det) < 1e-8 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        // (b1, b2) 是重心坐标，击中点为 (1 - b1 - b2) * p0 + b1 * p1 + b2 * p2
+        let tvec = ray.origin - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(edge1);
+        let b2 = ray.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(qvec) * inv_det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        let mut hit = Hit::new(t, ray.at(t));
+        let geometric_normal = edge1.cross(edge2).to_unit_vector();
+        hit.set_face_normal(ray, geometric_normal);
+
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let normals = &self.mesh.normals;
+        if !normals.is_empty() {
+            // 着色法线由三个顶点法线按重心坐标插值，并翻到和几何法线同一侧
+            let shading = (b0 * normals[i0] + b1 * normals[i1] + b2 * normals[i2]).to_unit_vector();
+            let face = hit.normal.unwrap();
+            hit.normal = Some(if shading.dot(face) < 0.0 {
+                -shading
+            } else {
+                shading
+            });
+        }
+        let uvs = &self.mesh.uvs;
+        if uvs.is_empty() {
+            hit.u = b1;
+            hit.v = b2;
+        } else {
+            hit.u = b0 * uvs[i0].0 + b1 * uvs[i1].0 + b2 * uvs[i2].0;
+            hit.v = b0 * uvs[i0].1 + b1 * uvs[i1].1 + b2 * uvs[i2].1;
+        }
+        hit.material = Some(self.mesh.material.clone());
+        Some(hit)
+    }
+
+    fn aabb(&self) -> Aabb {
+        let (p0, p1, p2) = self.vertices();
+        // 和坐标轴平行的三角形包围盒厚度为 0，稍微撑开一点
+        let padding = Vec3(1e-4, 1e-4, 1e-4);
+        let bbox = Aabb::from_points(p0, p1).grow(p2);
+        Aabb::new(bbox.min - padding, bbox.max + padding)
+    }
+
+    fn is_light(&self) -> bool {
+        self.mesh.material.is_emissive()
+    }
+
+    // 面积采样换算到立体角，余弦使用几何法线而不是插值的着色法线
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let rec = match self.hit(0.001, f32::MAX, &Ray::new(origin, direction)) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let (p0, p1, p2) = self.vertices();
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * cross.length();
+        let cosine = cross.to_unit_vector().dot(direction).abs() / direction.length();
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        distance_squared / (cosine * area)
+    }
+
+    // 在三角形上均匀采样一个点
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (p0, p1, p2) = self.vertices();
+        let s = random_f32().sqrt();
+        let b1 = s * random_f32();
+        let b0 = 1.0 - s;
+        b0 * p0 + b1 * p1 + (1.0 - b0 - b1) * p2 - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn mesh(normals: Vec<Vec3>, uvs: Vec<(f32, f32)>) -> Arc<TriangleMesh> {
+        let positions = vec![
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(1.0, 0.0, 0.0),
+            Vec3(0.0, 1.0, 0.0),
+        ];
+        let material = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        Arc::new(TriangleMesh::new(positions, normals, uvs, vec![[0, 1, 2]], material).unwrap())
+    }
+
+    fn shoot(mesh: &Arc<TriangleMesh>, x: f32, y: f32) -> Option<Hit> {
+        let triangle = Triangle {
+            mesh: mesh.clone(),
+            index: 0,
+        };
+        let ray = Ray::new(Vec3(x, y, 1.0), Vec3(0.0, 0.0, -1.0));
+        triangle.hit(0.001, f32::MAX, &ray)
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn ray_hits_inside_edges_and_vertices_and_misses_outside() {
+        let mesh = mesh(vec![], vec![]);
+        let hit = shoot(&mesh, 0.25, 0.5).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6);
+        assert!(close(hit.p, Vec3(0.25, 0.5, 0.0)));
+        assert!(close(hit.normal.unwrap(), Vec3(0.0, 0.0, 1.0)));
+        assert_eq!(hit.front_face, Some(true));
+        // 没有 UV 时使用重心坐标
+        assert!((hit.u - 0.25).abs() < 1e-6 && (hit.v - 0.5).abs() < 1e-6);
+
+        for (x, y) in [(0.5, 0.0), (0.0, 0.5), (0.5, 0.5), (0.0, 0.0), (1.0, 0.0)] {
+            assert!(shoot(&mesh, x, y).is_some(), "({}, {})", x, y);
+        }
+        for (x, y) in [(0.6, 0.6), (-0.1, 0.5), (0.5, -0.1), (2.0, 2.0)] {
+            assert!(shoot(&mesh, x, y).is_none(), "({}, {})", x, y);
+        }
+        // 背面击中时法线朝向光线
+        let triangle = Triangle { mesh, index: 0 };
+        let ray = Ray::new(Vec3(0.2, 0.2, -1.0), Vec3(0.0, 0.0, 1.0));
+        let hit = triangle.hit(0.001, f32::MAX, &ray).unwrap();
+        assert_eq!(hit.front_face, Some(false));
+        assert!(close(hit.normal.unwrap(), Vec3(0.0, 0.0, -1.0)));
+        // 平行的光线和 t 范围之外的击中点
+        let parallel = Ray::new(Vec3(-1.0, 0.2, 0.0), Vec3(1.0, 0.0, 0.0));
+        assert!(triangle.hit(0.001, f32::MAX, &parallel).is_none());
+        assert!(triangle.hit(0.001, 0.5, &ray).is_none());
+    }
+
+    #[test]
+    fn shading_normals_and_uvs_are_interpolated() {
+        let normals = vec![
+            Vec3(0.0, 0.0, 1.0),
+            Vec3(1.0, 0.0, 1.0).to_unit_vector(),
+            Vec3(0.0, 1.0, 1.0).to_unit_vector(),
+        ];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.2), (0.4, 1.0)];
+        let mesh = mesh(normals.clone(), uvs);
+        let (b1, b2) = (0.25, 0.5);
+        let hit = shoot(&mesh, b1, b2).unwrap();
+        let b0 = 1.0 - b1 - b2;
+        let expected = (b0 * normals[0] + b1 * normals[1] + b2 * normals[2]).to_unit_vector();
+        assert!(close(hit.normal.unwrap(), expected), "{:?}", hit.normal);
+        assert!((hit.u - (b1 * 1.0 + b2 * 0.4)).abs() < 1e-6);
+        assert!((hit.v - (b1 * 0.2 + b2 * 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn invalid_buffers_are_rejected() {
+        let material = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let positions = vec![Vec3(0.0, 0.0, 0.0); 3];
+        let result = TriangleMesh::new(
+            positions.clone(),
+            vec![Vec3(0.0, 0.0, 1.0); 2],
+            vec![],
+            vec![[0, 1, 2]],
+            material.clone(),
+        );
+        assert_eq!(
+            result.err(),
+            Some(MeshError::AttributeCount {
+                attribute: "normals",
+                expected: 3,
+                found: 2
+            })
+        );
+        let result = TriangleMesh::new(
+            positions,
+            vec![],
+            vec![],
+            vec![[0, 1, 2], [2, 3, 1]],
+            material,
+        );
+        assert_eq!(
+            result.err(),
+            Some(MeshError::IndexOutOfRange {
+                triangle: 1,
+                index: 3,
+                vertices: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::vec::Vec3;
+use std::f32::consts::PI;
+
+/// 以法线为 z 轴的正交坐标系，微表面的计算都在这个局部坐标系里进行
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub s: Vec3,
+    pub t: Vec3,
+    pub n: Vec3,
+}
+
+impl Frame {
+    /// Duff 等人的无分支构造方法，`n` 必须是单位向量
+    pub fn from_normal(n: Vec3) -> Frame {
+        let sign = if n.z() >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + n.z());
+        let b = n.x() * n.y() * a;
+        Frame {
+            s: Vec3(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+            t: Vec3(b, sign + n.y() * n.y() * a, -n.y()),
+            n,
+        }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.0 * self.s + v.1 * self.t + v.2 * self.n
+    }
+}
+
+/// 小于这个值的 alpha 当作理想镜面
+pub const MIN_ALPHA: f32 = 1e-3;
+
+/// 各向同性的 GGX (Trowbridge-Reitz) 法线分布，`alpha` 是分布的宽度
+/// 下面的方向都在局部坐标系中，z 轴是宏观法线
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha: f32,
+}
+
+impl Ggx {
+    pub fn new(alpha: f32) -> Ggx {
+        Ggx { alpha }
+    }
+
+    /// 法线分布函数 D(m)
+    pub fn d(&self, m: Vec3) -> f32 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let cos2 = m.z() * m.z();
+        let t = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (PI * t * t)
+    }
+
+    /// Smith 遮蔽函数中的 Λ(w)
+    pub fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// 高度相关的遮蔽-阴影函数
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// 从 `wo` 看过去可见的法线的分布 D_wo(m) = G1(wo) max(0, wo·m) D(m) / wo.z
+    pub fn visible_pdf(&self, wo: Vec3, m: Vec3) -> f32 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.z()
+    }
+
+    /// 按可见法线采样再反射得到 `wi` 的概率密度，两个方向都在上半球
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let h = (wo + wi).to_unit_vector();
+        let cos = wo.dot(h);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        self.visible_pdf(wo, h) / (4.0 * cos)
+    }
+
+    /// alpha 太小时分布退化成理想镜面，只能当作 delta 分布处理
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < MIN_ALPHA
+    }
+
+    /// Heitz 2018 的可见法线采样，`wo` 必须在上半球
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        // 拉伸成 alpha = 1 的半球
+        let vh = Vec3(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).to_unit_vector();
+        let length_squared = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+        // 在投影后的半圆盘上均匀采样
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * libm::cosf(phi);
+        let p2 = r * libm::sinf(phi);
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * p2;
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        // 再压缩回原来的分布
+        Vec3(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(1e-6)).to_unit_vector()
+    }
+}
+
+/// 导体的菲涅尔反射率，复折射率为 eta + i k，`cos_i` 是入射角的余弦
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Vec3(
+        channel(eta.0, k.0),
+        channel(eta.1, k.1),
+        channel(eta.2, k.2),
+    )
+}
+
+/// 绝缘体的菲涅尔反射率，`eta` 是透射一侧与入射一侧折射率之比
+/// 发生全反射时返回 1
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// `wo` 经过法线为 `m` 的微表面反射
+pub fn reflect(wo: Vec3, m: Vec3) -> Vec3 {
+    2.0 * wo.dot(m) * m - wo
+}
+
+/// `wo` 经过法线为 `m` 的微表面折射，`wo` 和 `m` 在同一侧，全反射时返回 None
+pub fn refract(wo: Vec3, m: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = wo.dot(m);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+/// 透射时的微表面法线，朝向 `wo` 一侧，`wo` 在上半球、`wi` 在下半球
+/// 两个方向不满足折射关系时返回 None
+pub fn transmission_normal(wo: Vec3, wi: Vec3, eta: f32) -> Option<Vec3> {
+    let m = (wo + eta * wi).to_unit_vector();
+    let m = if m.z() < 0.0 { -m } else { m };
+    if !m.0.is_finite() || wo.dot(m) <= 0.0 || wi.dot(m) >= 0.0 {
+        return None;
+    }
+    Some(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn projected_normal_distribution_integrates_to_one() {
+        // ∫ D(m) cos(θm) dm = 1，用均匀半球采样估计
+        let mut rng = StdRng::seed_from_u64(3);
+        for alpha in [0.1, 0.4, 1.0] {
+            let ggx = Ggx::new(alpha);
+            let count = 400_000;
+            let mut sum = 0.0f64;
+            for _ in 0..count {
+                let z: f32 = rng.gen();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let m = Vec3(r * phi.cos(), r * phi.sin(), z);
+                sum += (ggx.d(m) * m.z() * 2.0 * PI) as f64;
+            }
+            let estimate = (sum / count as f64) as f32;
+            assert!(
+                (estimate - 1.0).abs() < 0.03,
+                "alpha {} estimate {}",
+                alpha,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn visible_normal_samples_follow_visible_pdf() {
+        // 采样得到的 E[m.z] 和按 D_wo 积分得到的值一致
+        let mut rng = StdRng::seed_from_u64(4);
+        let ggx = Ggx::new(0.5);
+        for wo in [
+            Vec3(0.0, 0.0, 1.0),
+            Vec3(0.6, 0.0, 0.8),
+            Vec3(0.0, -0.95, 0.1).to_unit_vector(),
+        ] {
+            let count = 400_000;
+            let mut sampled = 0.0f64;
+            let mut integrated = 0.0f64;
+            for _ in 0..count {
+                let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+                assert!(m.z() > 0.0 && wo.dot(m) > -1e-4);
+                sampled += m.z() as f64;
+
+                let z: f32 = rng.gen();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let m = Vec3(r * phi.cos(), r * phi.sin(), z);
+                integrated += (ggx.visible_pdf(wo, m) * m.z() * 2.0 * PI) as f64;
+            }
+            let sampled = (sampled / count as f64) as f32;
+            let integrated = (integrated / count as f64) as f32;
+            assert!(
+                (sampled - integrated).abs() < 0.01,
+                "wo {:?} sampled {} integrated {}",
+                wo,
+                sampled,
+                integrated
+            );
+        }
+    }
+}
@@ -0,0 +1,565 @@
+use crate::hdr::HdrError;
+use crate::hit::HittableList;
+use crate::materials::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::{MeshError, TriangleMesh};
+use crate::texture::{ImageTexture, WrapMode};
+use crate::vec::Vec3;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 没有 `usemtl` 的面使用的默认漫反射颜色
+const DEFAULT_ALBEDO: Vec3 = Vec3(0.8, 0.8, 0.8);
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Image(HdrError),
+    Mesh(MeshError),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Image(e) => e.fmt(f),
+            ObjError::Mesh(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Image(e) => Some(e),
+            ObjError::Mesh(e) => Some(e),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// 逐行解析时记录当前位置，方便报错
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl LineParser<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+
+    fn float(&self, keyword: &str, token: Option<&str>) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("`{}` is missing a value", keyword)))?;
+        token
+            .parse::<f32>()
+            .map_err(|_| self.error(format!("`{}` has invalid number `{}`", keyword, token)))
+    }
+
+    fn vec3<'t>(
+        &self,
+        keyword: &str,
+        tokens: &mut impl Iterator<Item = &'t str>,
+    ) -> Result<Vec3, ObjError> {
+        Ok(Vec3(
+            self.float(keyword, tokens.next())?,
+            self.float(keyword, tokens.next())?,
+            self.float(keyword, tokens.next())?,
+        ))
+    }
+}
+
+/// MTL 文件中的一个材质，只保留能映射到现有材质的参数
+struct MtlMaterial {
+    diffuse: Vec3,
+    specular: Vec3,
+    shininess: f32,
+    dissolve: f32,
+    ior: Option<f32>,
+    // 自发光 (Ke)
+    emission: Vec3,
+    // 漫反射贴图 (map_Kd)
+    diffuse_map: Option<Arc<ImageTexture>>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            diffuse: DEFAULT_ALBEDO,
+            specular: Vec3(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            dissolve: 1.0,
+            ior: None,
+            emission: Vec3(0.0, 0.0, 0.0),
+            diffuse_map: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// 有 Ke 的当作光源，半透明 (d < 1) 的当作玻璃，高光比漫反射强的当作金属，其余为漫反射
+    /// 有漫反射贴图时用贴图代替 Kd
+    fn to_material(&self) -> Arc<dyn Material> {
+        let max = |c: Vec3| c.0.max(c.1).max(c.2);
+        if max(self.emission) > 0.0 {
+            Arc::new(DiffuseLight::new(self.emission))
+        } else if self.dissolve < 1.0 {
+            let ior = self.ior.filter(|&ior| ior > 1.0).unwrap_or(1.5);
+            Arc::new(Dielectric::new(ior))
+        } else if max(self.specular) > max(self.diffuse) {
+            // 把 Phong 指数换算成粗糙度
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            Arc::new(Metal::new(self.specular, fuzz))
+        } else if let Some(map) = &self.diffuse_map {
+            Arc::new(Lambertian::textured(map.clone()))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+/// 解析贴图语句的参数，返回文件名和环绕方式
+/// 只识别 `-clamp on|off`，其他选项连同它们的参数一起跳过
+fn parse_map(parser: &LineParser, tokens: Vec<&str>) -> Result<(String, WrapMode), ObjError> {
+    let mut wrap = WrapMode::Repeat;
+    let mut i = 0;
+    while i + 1 < tokens.len() && tokens[i].starts_with('-') {
+        let arguments = match tokens[i] {
+            "-clamp" => {
+                wrap = if tokens[i + 1] == "on" {
+                    WrapMode::Clamp
+                } else {
+                    WrapMode::Repeat
+                };
+                1
+            }
+            "-o" | "-s" | "-t" => (tokens[i + 1..tokens.len() - 1])
+                .iter()
+                .take(3)
+                .take_while(|t| t.parse::<f32>().is_ok())
+                .count(),
+            "-mm" => 2,
+            _ => 1,
+        };
+        i += 1 + arguments;
+    }
+    let name = tokens.get(i..).unwrap_or_default().join(" ");
+    if name.is_empty() {
+        return Err(parser.error("texture map is missing a file name".to_string()));
+    }
+    Ok((name, wrap))
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let source = read_file(path)?;
+    let mut parsed: Vec<(String, MtlMaterial)> = vec![];
+    let mut parser = LineParser { path, line: 0 };
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(parser.error("`newmtl` is missing a name".to_string()));
+            }
+            parsed.push((name, MtlMaterial::default()));
+            continue;
+        }
+        let current = match parsed.last_mut() {
+            Some((_, material)) => material,
+            None if matches!(
+                keyword,
+                "Kd" | "Ks" | "Ke" | "Ns" | "d" | "Tr" | "Ni" | "map_Kd"
+            ) =>
+            {
+                return Err(parser.error(format!("`{}` appears before any `newmtl`", keyword)))
+            }
+            None => continue,
+        };
+        match keyword {
+            "Kd" => current.diffuse = parser.vec3(keyword, &mut tokens)?,
+            "Ks" => current.specular = parser.vec3(keyword, &mut tokens)?,
+            "Ke" => {
+                let emission = parser.vec3(keyword, &mut tokens)?;
+                let valid = [emission.0, emission.1, emission.2]
+                    .iter()
+                    .all(|c| c.is_finite() && *c >= 0.0);
+                if !valid {
+                    return Err(parser.error("`Ke` must be finite and not negative".to_string()));
+                }
+                current.emission = emission;
+            }
+            "Ns" => current.shininess = parser.float(keyword, tokens.next())?,
+            "d" => current.dissolve = parser.float(keyword, tokens.next())?,
+            "Tr" => current.dissolve = 1.0 - parser.float(keyword, tokens.next())?,
+            "Ni" => current.ior = Some(parser.float(keyword, tokens.next())?),
+            "map_Kd" => {
+                let (name, wrap) = parse_map(&parser, tokens.collect())?;
+                let directory = path.parent().unwrap_or_else(|| Path::new(""));
+                let texture =
+                    ImageTexture::load(&directory.join(name), wrap).map_err(ObjError::Image)?;
+                current.diffuse_map = Some(Arc::new(texture));
+            }
+            // 其他贴图、环境光等暂不支持
+            _ => {}
+        }
+    }
+    Ok(parsed
+        .into_iter()
+        .map(|(name, material)| (name, material.to_material()))
+        .collect())
+}
+
+/// 一个使用同一材质的面的集合，构建时对 (v, vt, vn) 组合去重
+struct MeshBuilder {
+    material: Arc<dyn Material>,
+    vertex_map: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    keys: Vec<(usize, Option<usize>, Option<usize>)>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material>) -> MeshBuilder {
+        MeshBuilder {
+            material,
+            vertex_map: HashMap::new(),
+            keys: vec![],
+            indices: vec![],
+        }
+    }
+
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>)) -> usize {
+        let keys = &mut self.keys;
+        *self.vertex_map.entry(key).or_insert_with(|| {
+            keys.push(key);
+            keys.len() - 1
+        })
+    }
+
+    fn build(
+        self,
+        positions: &[Vec3],
+        uvs: &[(f32, f32)],
+        normals: &[Vec3],
+    ) -> Result<TriangleMesh, MeshError> {
+        // 只有所有顶点都带法线 / UV 时才使用它们
+        let mesh_normals = if self.keys.iter().all(|k| k.2.is_some()) {
+            self.keys.iter().map(|k| normals[k.2.unwrap()]).collect()
+        } else {
+            vec![]
+        };
+        let mesh_uvs = if self.keys.iter().all(|k| k.1.is_some()) {
+            self.keys.iter().map(|k| uvs[k.1.unwrap()]).collect()
+        } else {
+            vec![]
+        };
+        TriangleMesh::new(
+            self.keys.iter().map(|k| positions[k.0]).collect(),
+            mesh_normals,
+            mesh_uvs,
+            self.indices,
+            self.material,
+        )
+    }
+}
+
+/// 把 OBJ 中从 1 开始（或为负数、从末尾倒数）的下标转换成从 0 开始的下标
+fn resolve_index(
+    parser: &LineParser,
+    token: &str,
+    count: usize,
+    kind: &str,
+) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| parser.error(format!("invalid {} index `{}`", kind, token)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(parser.error(format!(
+            "{} index {} is out of range ({} defined so far)",
+            kind, index, count
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+/// 读取 `.obj` 文件及其引用的 `.mtl` 文件，每个材质生成一个三角形网格
+pub fn load_obj(path: &Path) -> Result<Vec<Arc<TriangleMesh>>, ObjError> {
+    let source = read_file(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new(DEFAULT_ALBEDO));
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f32, f32)> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut builders: Vec<MeshBuilder> = vec![MeshBuilder::new(default_material)];
+    let mut builder_by_material: HashMap<String, usize> = HashMap::new();
+    let mut current = 0;
+
+    let mut parser = LineParser { path, line: 0 };
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        match keyword {
+            "v" => positions.push(parser.vec3(keyword, &mut tokens)?),
+            "vn" => normals.push(parser.vec3(keyword, &mut tokens)?),
+            "vt" => {
+                let u = parser.float(keyword, tokens.next())?;
+                let v = match tokens.next() {
+                    Some(token) => parser.float(keyword, Some(token))?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                let mut face = vec![];
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let v =
+                        resolve_index(&parser, parts.next().unwrap(), positions.len(), "vertex")?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(part) => Some(resolve_index(&parser, part, uvs.len(), "texture")?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(part) => Some(resolve_index(&parser, part, normals.len(), "normal")?),
+                    };
+                    if parts.next().is_some() {
+                        return Err(parser.error(format!("malformed face vertex `{}`", token)));
+                    }
+                    face.push(builders[current].vertex((v, vt, vn)));
+                }
+                if face.len() < 3 {
+                    return Err(parser.error(format!(
+                        "face has {} vertices, at least 3 are required",
+                        face.len()
+                    )));
+                }
+                // 多边形按扇形拆成三角形
+                for i in 1..face.len() - 1 {
+                    builders[current]
+                        .indices
+                        .push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "mtllib" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return Err(parser.error("`mtllib` is missing a file name".to_string()));
+                }
+                // 找不到材质库时报告引用它的那一行
+                let library = load_mtl(&directory.join(&name)).map_err(|e| match e {
+                    ObjError::Io { source, .. } => parser.error(format!(
+                        "failed to read material library `{}`: {}",
+                        name, source
+                    )),
+                    e => e,
+                })?;
+                materials.extend(library);
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let material = materials
+                    .get(&name)
+                    .ok_or_else(|| parser.error(format!("unknown material `{}`", name)))?;
+                current = *builder_by_material.entry(name).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(material.clone()));
+                    builders.len() - 1
+                });
+            }
+            // 分组、平滑组、线段、曲面等都不影响渲染
+            _ => {}
+        }
+    }
+
+    builders
+        .into_iter()
+        .filter(|builder| !builder.indices.is_empty())
+        .map(|builder| {
+            let mesh = builder
+                .build(&positions, &uvs, &normals)
+                .map_err(ObjError::Mesh)?;
+            Ok(Arc::new(mesh))
+        })
+        .collect()
+}
+
+/// 读取 `.obj` 文件，把所有三角形放进一个 `HittableList`
+pub fn load_obj_list(path: &Path) -> Result<HittableList, ObjError> {
+    let mut world = HittableList::new();
+    for mesh in load_obj(path)? {
+        world.objects.extend(TriangleMesh::triangles(&mesh));
+    }
+    Ok(world)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::Hit;
+    use crate::ray::Ray;
+
+    /// 把文件写到临时目录里，返回第一个文件的路径
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("raytrace-obj-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (name, contents) in files {
+            std::fs::write(directory.join(name), contents).unwrap();
+        }
+        directory.join(files[0].0)
+    }
+
+    fn parse_error(test: &str, files: &[(&str, &str)]) -> (usize, String) {
+        let path = write_files(test, files);
+        let result = load_obj(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        match result {
+            Err(ObjError::Parse {
+                path: error_path,
+                line,
+                message,
+            }) => {
+                assert_eq!(error_path.file_name(), path.file_name());
+                (line, message)
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("{} should not load", test),
+        }
+    }
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+
+    #[test]
+    fn malformed_files_report_line_and_message() {
+        let (line, message) = parse_error("float", &[("a.obj", "v 0 0 0\nv 1 x 0\n")]);
+        assert_eq!((line, message.as_str()), (2, "`v` has invalid number `x`"));
+
+        let source = format!("{}f 1 2 0\n", TRIANGLE);
+        let (line, message) = parse_error("zero", &[("a.obj", &source)]);
+        assert_eq!(
+            (line, message.as_str()),
+            (4, "vertex index 0 is out of range (3 defined so far)")
+        );
+
+        let source = format!("{}\nf 1 2 4\n", TRIANGLE);
+        let (line, message) = parse_error("range", &[("a.obj", &source)]);
+        assert_eq!(
+            (line, message.as_str()),
+            (5, "vertex index 4 is out of range (3 defined so far)")
+        );
+
+        let source = format!("mtllib missing.mtl\n{}", TRIANGLE);
+        let (line, message) = parse_error("mtl", &[("a.obj", &source)]);
+        assert_eq!(line, 1);
+        assert!(
+            message.starts_with("failed to read material library `missing.mtl`"),
+            "{}",
+            message
+        );
+
+        let source = format!("mtllib a.mtl\n{}usemtl steel\nf 1 2 3\n", TRIANGLE);
+        let (line, message) = parse_error(
+            "usemtl",
+            &[("a.obj", &source), ("a.mtl", "newmtl paint\nKd 1 0 0\n")],
+        );
+        assert_eq!((line, message.as_str()), (5, "unknown material `steel`"));
+    }
+
+    fn scatter(material: &MtlMaterial) -> crate::materials::Scatter {
+        let mut rec = Hit::new(1.0, Vec3(0.0, 0.0, 0.0));
+        let ray = Ray::new(Vec3(-1.0, 0.0, 1.0), Vec3(1.0, 0.0, -1.0));
+        rec.set_face_normal(&ray, Vec3(0.0, 0.0, 1.0));
+        material.to_material().scatter(&ray, &rec).unwrap()
+    }
+
+    #[test]
+    fn mtl_parameters_map_to_materials() {
+        // Kd 为主的是漫反射
+        let diffuse = MtlMaterial {
+            diffuse: Vec3(0.8, 0.1, 0.1),
+            specular: Vec3(0.2, 0.2, 0.2),
+            ..MtlMaterial::default()
+        };
+        let s = scatter(&diffuse);
+        assert!(!s.delta);
+        assert!((s.attenuation - Vec3(0.8, 0.1, 0.1)).length() < 1e-6);
+
+        // Ks 比 Kd 强的是金属，Ns 越大越光滑
+        let metal = MtlMaterial {
+            diffuse: Vec3(0.1, 0.1, 0.1),
+            specular: Vec3(0.9, 0.7, 0.5),
+            shininess: 1e6,
+            ..MtlMaterial::default()
+        };
+        let s = scatter(&metal);
+        assert!((s.attenuation - Vec3(0.9, 0.7, 0.5)).length() < 1e-6);
+        let mirror = Vec3(1.0, 0.0, 1.0).to_unit_vector();
+        assert!(s.ray.direction.to_unit_vector().dot(mirror) > 0.99);
+
+        // 半透明的是玻璃，使用 Ni 作为折射率
+        let glass = MtlMaterial {
+            dissolve: 0.5,
+            ior: Some(1.33),
+            ..MtlMaterial::default()
+        };
+        let s = scatter(&glass);
+        assert!(s.delta);
+        assert!((s.attenuation - Vec3(1.0, 1.0, 1.0)).length() < 1e-6);
+
+        // 有 Ke 的是光源
+        let light = MtlMaterial {
+            emission: Vec3(4.0, 3.0, 2.0),
+            ..MtlMaterial::default()
+        };
+        let light = light.to_material();
+        assert!(light.is_emissive());
+        let emitted = light.emitted(0.0, 0.0, Vec3(0.0, 0.0, 0.0));
+        assert!((emitted - Vec3(4.0, 3.0, 2.0)).length() < 1e-6);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::renderer::Framebuffer;
+use crate::tonemap::ToneMapping;
+use exr::prelude::f16;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// OpenEXR 每个通道的精度
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+/// 支持的输出格式，PNG 是经过色调映射的 8 位 sRGB 图像，其余两种保存线性浮点数据
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Exr,
+    Pfm,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    UnsupportedFormat(PathBuf),
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Encode {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::UnsupportedFormat(path) => write!(
+                f,
+                "{}: unsupported image format, expected .png, .exr or .pfm",
+                path.display()
+            ),
+            OutputError::Io { path, source } => {
+                write!(f, "failed to write {}: {}", path.display(), source)
+            }
+            OutputError::Encode { path, message } => {
+                write!(f, "failed to write {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OutputError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// 写出图像时的设置，色调映射只影响 PNG
+#[derive(Clone, Copy, Debug)]
+pub struct OutputSettings {
+    pub exr_precision: ExrPrecision,
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for OutputSettings {
+    fn default() -> OutputSettings {
+        OutputSettings {
+            exr_precision: ExrPrecision::Float,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+/// 根据扩展名选择格式写出图像
+pub fn write_image(
+    path: &Path,
+    framebuffer: &Framebuffer,
+    settings: &OutputSettings,
+) -> Result<(), OutputError> {
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => write_png(path, framebuffer, &settings.tone_mapping),
+        Some(ImageFormat::Exr) => write_exr(path, framebuffer, settings.exr_precision),
+        Some(ImageFormat::Pfm) => write_pfm(path, framebuffer),
+        None => Err(OutputError::UnsupportedFormat(path.to_path_buf())),
+    }
+}
+
+pub fn write_png(
+    path: &Path,
+    framebuffer: &Framebuffer,
+    tone_mapping: &ToneMapping,
+) -> Result<(), OutputError> {
+    let pixels = framebuffer.to_rgb8(tone_mapping);
+    lodepng::encode_file(
+        path,
+        &pixels,
+        framebuffer.width,
+        framebuffer.height,
+        lodepng::ColorType::RGB,
+        8,
+    )
+    .map_err(|e| OutputError::Encode {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+pub fn write_exr(
+    path: &Path,
+    framebuffer: &Framebuffer,
+    precision: ExrPrecision,
+) -> Result<(), OutputError> {
+    let pixel = |x: usize, y: usize| framebuffer.pixels[y * framebuffer.width + x];
+    let result = match precision {
+        ExrPrecision::Half => {
+            exr::prelude::write_rgb_file(path, framebuffer.width, framebuffer.height, |x, y| {
+                let p = pixel(x, y);
+                (f16::from_f32(p.0), f16::from_f32(p.1), f16::from_f32(p.2))
+            })
+        }
+        ExrPrecision::Float => {
+            exr::prelude::write_rgb_file(path, framebuffer.width, framebuffer.height, |x, y| {
+                let p = pixel(x, y);
+                (p.0, p.1, p.2)
+            })
+        }
+    };
+    result.map_err(|e| match e {
+        exr::error::Error::Io(source) => OutputError::Io {
+            path: path.to_path_buf(),
+            source,
+        },
+        e => OutputError::Encode {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        },
+    })
+}
+
+/// Portable Float Map：小端序 32 位浮点，行从下到上存放
+pub fn write_pfm(path: &Path, framebuffer: &Framebuffer) -> Result<(), OutputError> {
+    let io_error = |source| OutputError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = File::create(path).map_err(io_error)?;
+    let mut writer = BufWriter::new(file);
+    write!(
+        writer,
+        "PF\n{} {}\n-1.0\n",
+        framebuffer.width, framebuffer.height
+    )
+    .map_err(io_error)?;
+    for row in framebuffer.pixels.chunks(framebuffer.width).rev() {
+        for pixel in row {
+            for value in [pixel.0, pixel.1, pixel.2] {
+                writer.write_all(&value.to_le_bytes()).map_err(io_error)?;
+            }
+        }
+    }
+    writer.flush().map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr;
+    use crate::vec::Vec3;
+
+    #[test]
+    fn pfm_round_trips_bottom_to_top() {
+        let mut framebuffer = Framebuffer::new(2, 3);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = Vec3(i as f32, 0.5 * i as f32, -1.25 * i as f32);
+        }
+        let path = std::env::temp_dir().join(format!("raytrace-{}-output.pfm", std::process::id()));
+        write_pfm(&path, &framebuffer).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let image = hdr::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // 负的比例因子表示小端序
+        let header = b"PF\n2 3\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + 2 * 3 * 3 * 4);
+        // 文件中的第一行是图像最下面一行
+        let first = f32::from_le_bytes(data[header.len()..header.len() + 4].try_into().unwrap());
+        assert_eq!(first, framebuffer.pixels[4].0);
+
+        let image = image.unwrap();
+        assert_eq!((image.width, image.height), (2, 3));
+        for (read, written) in image.pixels.iter().zip(&framebuffer.pixels) {
+            assert_eq!((read.0, read.1, read.2), (written.0, written.1, written.2));
+        }
+    }
+
+    #[test]
+    fn exr_round_trips_in_both_precisions() {
+        let mut framebuffer = Framebuffer::new(2, 3);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = Vec3(i as f32 / 3.0, 100.0 + i as f32, -0.1 * i as f32);
+        }
+        for (precision, name, sample_type) in [
+            (ExrPrecision::Half, "half", exr::prelude::SampleType::F16),
+            (ExrPrecision::Float, "float", exr::prelude::SampleType::F32),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "raytrace-{}-output-{}.exr",
+                std::process::id(),
+                name
+            ));
+            write_exr(&path, &framebuffer, precision).unwrap();
+            let image = exr::prelude::read_first_rgba_layer_from_file(
+                &path,
+                |resolution, _| vec![(0.0, 0.0, 0.0); resolution.width() * resolution.height()],
+                |pixels: &mut Vec<(f32, f32, f32)>,
+                 position,
+                 (r, g, b, _a): (f32, f32, f32, f32)| {
+                    pixels[position.y() * 2 + position.x()] = (r, g, b)
+                },
+            );
+            std::fs::remove_file(&path).unwrap();
+            let image = image.unwrap();
+
+            let layer = &image.layer_data;
+            assert_eq!((layer.size.width(), layer.size.height()), (2, 3));
+            assert_eq!(layer.channel_data.channels.0.sample_type, sample_type);
+            // EXR 的行从上到下存放，和帧缓冲一致
+            let expected = |value: f32| match precision {
+                ExrPrecision::Half => f16::from_f32(value).to_f32(),
+                ExrPrecision::Float => value,
+            };
+            for (read, written) in layer.channel_data.pixels.iter().zip(&framebuffer.pixels) {
+                let written = (
+                    expected(written.0),
+                    expected(written.1),
+                    expected(written.2),
+                );
+                assert_eq!(*read, written, "{}", name);
+            }
+        }
+        // 半精度只有 11 位有效数字
+        assert_ne!(f16::from_f32(1.0 / 3.0).to_f32(), 1.0 / 3.0);
+    }
+}
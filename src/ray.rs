@@ -4,11 +4,21 @@ use crate::vec::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // 光线发出的时刻，用于运动模糊
+    pub time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Vec3, direction: Vec3, time: f32) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn at(&self, t: f32) -> Vec3 {
@@ -0,0 +1,529 @@
+use crate::background::{Background, EnvironmentMap};
+use crate::camera::Camera;
+use crate::hit::{Hit, Hittable};
+use crate::light::LightList;
+use crate::materials::Material;
+use crate::ray::Ray;
+use crate::tonemap::ToneMapping;
+use crate::utils;
+use crate::vec::Vec3;
+use lodepng::RGB;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+/// 图像和采样设置
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub aspect_ratio: f32,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    // 从第几次弹射开始做俄罗斯轮盘赌
+    pub rr_depth: usize,
+    // 相同的种子得到相同的图像，和线程数无关
+    pub seed: u64,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 1200,
+            aspect_ratio: 3.0 / 2.0,
+            samples_per_pixel: 500,
+            max_depth: 50,
+            rr_depth: 3,
+            seed: 0,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn height(&self) -> usize {
+        (self.width as f32 / self.aspect_ratio) as usize
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.height() < 2 {
+            return Err(format!(
+                "image height {} is too small for width {} and aspect ratio {}",
+                self.height(),
+                self.width,
+                self.aspect_ratio
+            ));
+        }
+        if self.rr_depth > self.max_depth {
+            return Err(format!(
+                "rr_depth {} must not be greater than max_depth {}",
+                self.rr_depth, self.max_depth
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 渲染结果：每个像素是采样平均后的线性颜色，按行从上到下存放
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Vec3(0.0, 0.0, 0.0); width * height],
+        }
+    }
+
+    /// 曝光、色调映射后转换成 sRGB 编码的 8 位颜色
+    pub fn to_rgb8(&self, tone_mapping: &ToneMapping) -> Vec<RGB<u8>> {
+        tone_mapping.map_to_rgb8(&self.pixels)
+    }
+}
+
+pub fn ray_color(ray: &Ray, world: &dyn Hittable, background: &Background, depth: usize) -> Vec3 {
+    trace(
+        ray,
+        world,
+        background,
+        &LightList::default(),
+        depth,
+        RenderSettings::default().rr_depth,
+    )
+}
+
+/// 多重重要性采样的幂启发式权重 (β = 2)
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// 按环境贴图的亮度分布采样一个方向，计算它对击中点的直接光照
+fn sample_environment(
+    map: &EnvironmentMap,
+    world: &dyn Hittable,
+    ray: &Ray,
+    rec: &Hit,
+    material: &dyn Material,
+) -> Vec3 {
+    let (direction, radiance, light_pdf) =
+        match map.sample(utils::random_f32(), utils::random_f32()) {
+            Some(sample) => sample,
+            None => return Vec3(0.0, 0.0, 0.0),
+        };
+    let wo = -ray.direction.to_unit_vector();
+    let bsdf_pdf = material.pdf(rec, direction, wo);
+    if bsdf_pdf <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let shadow_ray = Ray::with_time(rec.p, direction, ray.time);
+    // 穿过参与介质时光线只被部分遮挡
+    let transmittance = world.transmittance(0.001, f32::MAX, &shadow_ray);
+    if transmittance <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let weight = power_heuristic(light_pdf, bsdf_pdf);
+    (transmittance * weight / light_pdf) * (material.eval(rec, direction, wo) * radiance)
+}
+
+/// 随机选择一个光源采样一个方向，计算它对击中点的直接光照
+fn sample_lights(
+    lights: &LightList,
+    world: &dyn Hittable,
+    ray: &Ray,
+    rec: &Hit,
+    material: &dyn Material,
+) -> Vec3 {
+    let sample = match lights.sample(rec.p, ray.time) {
+        Some(sample) => sample,
+        None => return Vec3(0.0, 0.0, 0.0),
+    };
+    let wo = -ray.direction.to_unit_vector();
+    let bsdf_pdf = material.pdf(rec, sample.direction, wo);
+    if bsdf_pdf <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let shadow_ray = Ray::with_time(rec.p, sample.direction, ray.time);
+    // 阴影光线停在光源前面一点，不被光源自己挡住
+    let t_max = sample.distance * (1.0 - 1e-4);
+    let transmittance = world.transmittance(0.001, t_max, &shadow_ray);
+    if transmittance <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    // delta 光源不可能被散射光线击中，不需要多重重要性采样
+    let weight = if sample.delta {
+        1.0
+    } else {
+        power_heuristic(sample.pdf, bsdf_pdf)
+    };
+    (transmittance * weight / sample.pdf)
+        * (material.eval(rec, sample.direction, wo) * sample.radiance)
+}
+
+/// 沿着一条路径迭代累加光照，最多弹射 `max_depth` 次
+/// 弹射 `rr_depth` 次以后按路径权重做俄罗斯轮盘赌，存活的路径除以存活概率，结果仍然无偏
+fn trace(
+    ray: &Ray,
+    world: &dyn Hittable,
+    background: &Background,
+    lights: &LightList,
+    max_depth: usize,
+    rr_depth: usize,
+) -> Vec3 {
+    let mut radiance = Vec3(0.0, 0.0, 0.0);
+    let mut throughput = Vec3(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    // 上一次散射采到当前光线方向的概率密度，
+    // 在光线击中光源或者射向环境时用来和光源采样做多重重要性采样
+    let mut bsdf_pdf: Option<f32> = None;
+
+    for bounce in 0..max_depth {
+        let mut rec = match world.hit(0.001, f32::MAX, &ray) {
+            Some(rec) => rec,
+            None => {
+                let color = match (background, bsdf_pdf) {
+                    (Background::Environment(map), Some(bsdf_pdf)) => {
+                        power_heuristic(bsdf_pdf, map.pdf(ray.direction))
+                            * map.radiance(ray.direction)
+                    }
+                    _ => background.color(ray.direction),
+                };
+                let distant = lights.escaped(ray.direction).fold(
+                    Vec3(0.0, 0.0, 0.0),
+                    |sum, (color, light_pdf)| {
+                        let weight = bsdf_pdf.map_or(1.0, |p| power_heuristic(p, light_pdf));
+                        sum + weight * color
+                    },
+                );
+                return radiance + throughput * (color + distant);
+            }
+        };
+        let material = rec.material.take().unwrap();
+        let mut emitted = material.emitted(rec.u, rec.v, rec.p);
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if material.is_emissive() && !lights.is_empty() {
+                emitted = power_heuristic(bsdf_pdf, lights.pdf(&ray, &rec)) * emitted;
+            }
+        }
+        radiance = radiance + throughput * emitted;
+        // 光源采样不依赖散射方向，散射采样被拒绝时也要算上；delta 分布的 pdf 为 0，不会有贡献
+        let mut direct = sample_lights(lights, world, &ray, &rec, material.as_ref());
+        if let Background::Environment(map) = background {
+            direct = direct + sample_environment(map, world, &ray, &rec, material.as_ref());
+        }
+        radiance = radiance + throughput * direct;
+        let scattered = match material.scatter(&ray, &rec) {
+            Some(scattered) => scattered,
+            None => break,
+        };
+        // delta 分布只能靠散射光线找到光源
+        bsdf_pdf = if scattered.delta {
+            None
+        } else {
+            let wi = scattered.ray.direction.to_unit_vector();
+            Some(material.pdf(&rec, wi, -ray.direction.to_unit_vector()))
+        };
+        throughput = throughput * scattered.attenuation;
+        if bounce + 1 >= rr_depth {
+            let survival = throughput.0.max(throughput.1).max(throughput.2).min(0.95);
+            if survival <= 0.0 || utils::random_f32() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+        ray = scattered.ray;
+    }
+    // 超过弹射次数或者被轮盘赌终止的路径不再收集光照
+    radiance
+}
+
+/// 多线程渲染器，不读写文件，也不输出进度
+pub struct Renderer {
+    pub settings: RenderSettings,
+    pub background: Background,
+    pub lights: LightList,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer {
+            settings,
+            background: Background::default(),
+            lights: LightList::default(),
+        }
+    }
+
+    /// 解析天空的太阳会在渲染时自动加入光源列表
+    pub fn with_background(mut self, background: Background) -> Renderer {
+        self.background = background;
+        self
+    }
+
+    /// 直接采样的光源，它们也必须在传给 `render` 的场景里
+    pub fn with_lights(mut self, lights: LightList) -> Renderer {
+        self.lights = lights;
+        self
+    }
+
+    /// 渲染时使用的光源：`lights` 加上解析天空的太阳
+    fn sampled_lights(&self) -> LightList {
+        match &self.background {
+            Background::PhysicalSky(sky) => self.lights.clone().with_analytic(vec![sky.sun()]),
+            _ => self.lights.clone(),
+        }
+    }
+
+    pub fn render(&self, world: &dyn Hittable, camera: &Camera) -> Framebuffer {
+        self.render_with_progress(world, camera, || {})
+    }
+
+    /// 每渲染完一行调用一次 `on_row`，调用可能来自任意线程
+    pub fn render_with_progress<F>(
+        &self,
+        world: &dyn Hittable,
+        camera: &Camera,
+        on_row: F,
+    ) -> Framebuffer
+    where
+        F: Fn() + Sync,
+    {
+        let settings = &self.settings;
+        let width = settings.width;
+        let height = settings.height();
+        let mut framebuffer = Framebuffer::new(width, height);
+        let lights = self.sampled_lights();
+
+        framebuffer
+            .pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(row, band)| {
+                // 每一行有自己的随机数生成器，结果和线程调度无关，
+                // 渲染线程原来的随机数状态也不受影响
+                let mut rng = SmallRng::seed_from_u64(
+                    settings.seed ^ (row as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15),
+                );
+                let y = height - row;
+                utils::with_rng(&mut rng, || {
+                    for (column, pixel) in band.iter_mut().enumerate() {
+                        let mut pixel_color = Vec3(0.0, 0.0, 0.0);
+                        for _s in 0..settings.samples_per_pixel {
+                            let u: f32 = (column as f32 + utils::random_f32()) / (width - 1) as f32;
+                            let v: f32 = (y as f32 + utils::random_f32()) / (height - 1) as f32;
+                            let r = camera.get_ray(u, v);
+                            pixel_color = pixel_color
+                                + trace(
+                                    &r,
+                                    world,
+                                    &self.background,
+                                    &lights,
+                                    settings.max_depth,
+                                    settings.rr_depth,
+                                );
+                        }
+                        *pixel = pixel_color / settings.samples_per_pixel as f32;
+                    }
+                });
+                on_row();
+            });
+
+        framebuffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::{HittableList, Sphere, XzRect};
+    use crate::materials::{DiffuseLight, Lambertian, RoughConductor, Scatter};
+    use crate::scene::CameraSettings;
+    use crate::sky::PreethamSky;
+    use crate::utils::seed_rng;
+    use std::sync::Arc;
+
+    /// 既发光又做漫反射的材质，用来做白炉测试
+    struct Glowing(Lambertian);
+
+    impl Material for Glowing {
+        fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<Scatter> {
+            self.0.scatter(r_in, rec)
+        }
+
+        fn eval(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+            self.0.eval(rec, wi, wo)
+        }
+
+        fn pdf(&self, rec: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+            self.0.pdf(rec, wi, wo)
+        }
+
+        fn emitted(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+            Vec3(1.0, 1.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        seed_rng(12);
+        // 在封闭的球里，每次弹射都收集 1 的亮度再乘以反射率 a，
+        // 弹射 n 次的期望是 1 + a + ... + a^(n-1)
+        let albedo = 0.5;
+        let world = Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Arc::new(Glowing(Lambertian::new(Vec3(albedo, albedo, albedo)))),
+        };
+        let background = Background::Solid(Vec3(0.0, 0.0, 0.0));
+        let lights = LightList::default();
+        let max_depth = 10;
+        let expected = (1.0 - libm::powf(albedo, max_depth as f32)) / (1.0 - albedo);
+        let radiance = |rr_depth: usize| {
+            let direction = Vec3::random_unit_vector();
+            let ray = Ray::new(Vec3(0.0, 0.0, 0.0), direction);
+            trace(&ray, &world, &background, &lights, max_depth, rr_depth).0
+        };
+
+        let without = radiance(max_depth);
+        assert!((without - expected).abs() < 1e-4, "{}", without);
+        let n = 20_000;
+        let with = (0..n).map(|_| radiance(0)).sum::<f32>() / n as f32;
+        assert!(
+            (with - expected).abs() < 0.03,
+            "with Russian roulette {} expected {}",
+            with,
+            expected
+        );
+    }
+
+    #[test]
+    fn physical_sky_adds_the_sun() {
+        let renderer = Renderer::new(RenderSettings::default());
+        assert!(renderer.sampled_lights().is_empty());
+        let sky = PreethamSky::new(30.0, 0.0, 3.0, 1.0);
+        // 背景和光源按任意顺序设置都会加上太阳
+        let renderer = Renderer::new(RenderSettings::default())
+            .with_background(Background::PhysicalSky(sky.clone()))
+            .with_lights(LightList::default());
+        assert_eq!(renderer.sampled_lights().len(), 1);
+        let sun = renderer
+            .sampled_lights()
+            .sample(Vec3(0.0, 0.0, 0.0), 0.0)
+            .unwrap();
+        assert!(sun.direction.dot(sky.sun_direction()) > 0.99);
+    }
+
+    #[test]
+    fn direct_light_on_rough_conductor_is_unbiased() {
+        seed_rng(15);
+        // 粗糙金属平面在掠射角下很多散射采样会落到表面以下，光源采样不能因此被丢掉
+        let objects: Vec<Box<dyn Hittable>> = vec![
+            Box::new(XzRect {
+                x0: -10.0,
+                x1: 10.0,
+                z0: -10.0,
+                z1: 10.0,
+                k: 0.0,
+                material: Arc::new(RoughConductor::new(
+                    Vec3(0.2, 0.4, 1.4),
+                    Vec3(3.9, 2.4, 1.8),
+                    0.6,
+                )),
+            }),
+            Box::new(XzRect {
+                x0: 1.0,
+                x1: 2.0,
+                z0: -0.5,
+                z1: 0.5,
+                k: 1.0,
+                material: Arc::new(DiffuseLight::new(Vec3(4.0, 4.0, 4.0))),
+            }),
+        ];
+        let (objects, lights) = LightList::extract(objects);
+        let world = HittableList { objects };
+        let background = Background::Solid(Vec3(0.0, 0.0, 0.0));
+        let ray = Ray::new(Vec3(-5.0, 0.5, 0.0), Vec3(5.0, -0.5, 0.0));
+
+        // 参考值只用光源采样估计直接光照
+        let rec = world.hit(0.001, f32::MAX, &ray).unwrap();
+        let material = rec.material.clone().unwrap();
+        let wo = -ray.direction.to_unit_vector();
+        let n = 100_000;
+        let mut expected = 0.0;
+        for _ in 0..n {
+            let sample = lights.sample(rec.p, 0.0).unwrap();
+            expected +=
+                (material.eval(&rec, sample.direction, wo) * sample.radiance).1 / sample.pdf;
+        }
+        let expected = expected / n as f32;
+
+        // 只弹射两次：击中平面，再击中光源或者射向黑色的背景
+        let estimate = (0..n)
+            .map(|_| trace(&ray, &world, &background, &lights, 2, 2).1)
+            .sum::<f32>()
+            / n as f32;
+        assert!(expected > 0.01, "{}", expected);
+        assert!(
+            (estimate - expected).abs() < 0.03 * expected,
+            "trace {} expected {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn rows_use_their_own_generators() {
+        let world = Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5))),
+        };
+        let settings = RenderSettings {
+            width: 12,
+            aspect_ratio: 1.5,
+            samples_per_pixel: 4,
+            seed: 7,
+            ..RenderSettings::default()
+        };
+        let camera = CameraSettings::default().build(settings.aspect_ratio);
+        let renderer = Renderer::new(settings).with_background(Background::sky());
+
+        let framebuffer = renderer.render(&world, &camera);
+        // 只有一个线程时每一行都在调用线程上渲染，结果相同，
+        // 而且渲染不改变这个线程原来的随机数序列
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let single = pool.install(|| {
+            seed_rng(14);
+            let expected = utils::random_f32();
+            seed_rng(14);
+            let framebuffer = renderer.render(&world, &camera);
+            assert_eq!(utils::random_f32(), expected);
+            framebuffer
+        });
+        for (a, b) in framebuffer.pixels.iter().zip(&single.pixels) {
+            assert_eq!((a.0, a.1, a.2), (b.0, b.1, b.2));
+        }
+    }
+
+    #[test]
+    fn rr_depth_must_not_exceed_max_depth() {
+        let mut settings = RenderSettings {
+            max_depth: 4,
+            rr_depth: 4,
+            ..RenderSettings::default()
+        };
+        assert!(settings.validate().is_ok());
+        settings.rr_depth = 5;
+        assert!(settings.validate().is_err());
+    }
+}
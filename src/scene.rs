@@ -0,0 +1,1853 @@
+use crate::aabb::Aabb;
+use crate::background::{Background, EnvironmentMap};
+use crate::bvh::BvhNode;
+use crate::camera::Camera;
+use crate::hdr::HdrError;
+use crate::hit::{
+    Cuboid, Hittable, HittableList, MovingSphere, Quad, Sphere, XyRect, XzRect, YzRect,
+};
+use crate::light::AnalyticLight;
+use crate::materials::{
+    conductor_ior, Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal, Principled,
+    RoughConductor, RoughDielectric,
+};
+use crate::medium::{ConstantMedium, DensityField, GridDensity, HeterogeneousMedium, NoiseDensity};
+use crate::mesh::TriangleMesh;
+use crate::obj;
+use crate::renderer::RenderSettings;
+use crate::sky::PreethamSky;
+use crate::texture::{
+    CheckerTexture, ImageTexture, MarbleTexture, NoiseTexture, SolidColor, Texture,
+    TurbulenceTexture, WrapMode,
+};
+use crate::transform::{Mat4, Transform};
+use crate::utils;
+use crate::vec::Vec3;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use toml::Spanned;
+
+/// 相机参数，宽高比来自 `RenderSettings`
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    // 快门打开和关闭的时刻，两者相同时没有运动模糊
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            look_from: Vec3(13.0, 2.0, 3.0),
+            look_at: Vec3(0.0, 0.0, 0.0),
+            vup: Vec3(0.0, 1.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        let view = self.look_at - self.look_from;
+        if view.near_zero() {
+            return Err("look_from and look_at must be different points".to_string());
+        }
+        if self.vup.cross(view).near_zero() {
+            return Err("vup must not be parallel to the viewing direction".to_string());
+        }
+        if self.shutter_close < self.shutter_open {
+            return Err("shutter_close must not be earlier than shutter_open".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+        )
+        .with_shutter(self.shutter_open, self.shutter_close)
+    }
+}
+
+/// 一个完整的场景：物体、相机和渲染设置
+pub struct Scene {
+    pub world: HittableList,
+    pub camera: CameraSettings,
+    pub render: RenderSettings,
+    pub background: Background,
+    /// 没有几何形状的光源
+    pub lights: Vec<AnalyticLight>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Mesh(obj::ObjError),
+    Image(HdrError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            SceneError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            SceneError::Mesh(e) => e.fmt(f),
+            SceneError::Image(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { .. } => None,
+            SceneError::Mesh(e) => Some(e),
+            SceneError::Image(e) => Some(e),
+        }
+    }
+}
+
+// 下面是场景文件的结构，所有设置项都是可选的
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    render: Option<Spanned<RenderSection>>,
+    camera: Option<Spanned<CameraSection>>,
+    background: Option<Spanned<SpannedTable>>,
+    // 纹理、材质和物体先按表读入，保留每个值的位置，再逐个转换成具体类型
+    #[serde(default)]
+    textures: BTreeMap<String, Spanned<SpannedTable>>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<SpannedTable>>,
+    #[serde(default)]
+    objects: Vec<Spanned<SpannedTable>>,
+    #[serde(default)]
+    lights: Vec<Spanned<SpannedTable>>,
+}
+
+type SpannedTable = BTreeMap<String, Spanned<toml::Value>>;
+
+/// 场景文件中的一个表，以及表中每个值的位置，出错时指向具体的值
+#[derive(Clone)]
+struct Entry {
+    table: toml::Table,
+    span: Range<usize>,
+    spans: BTreeMap<String, Range<usize>>,
+}
+
+impl Entry {
+    fn new(table: &Spanned<SpannedTable>) -> Entry {
+        Entry {
+            table: table
+                .get_ref()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.get_ref().clone()))
+                .collect(),
+            span: table.span(),
+            spans: table
+                .get_ref()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.span()))
+                .collect(),
+        }
+    }
+
+    /// 内联表中的值没有单独的位置，都使用整个表的位置
+    fn inline(table: toml::Table, span: Range<usize>) -> Entry {
+        Entry {
+            table,
+            span,
+            spans: BTreeMap::new(),
+        }
+    }
+
+    /// `key` 对应的值的位置，没有这个值时使用整个表的位置
+    fn at(&self, key: &str) -> Range<usize> {
+        self.spans.get(key).unwrap_or(&self.span).clone()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenderSection {
+    width: Option<Spanned<usize>>,
+    aspect_ratio: Option<Spanned<f32>>,
+    samples: Option<Spanned<usize>>,
+    max_depth: Option<Spanned<usize>>,
+    rr_depth: Option<Spanned<usize>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraSection {
+    look_from: Option<Vec3>,
+    look_at: Option<Vec3>,
+    vup: Option<Vec3>,
+    vfov: Option<Spanned<f32>>,
+    aperture: Option<Spanned<f32>>,
+    focus_dist: Option<Spanned<f32>>,
+    shutter_open: Option<f32>,
+    shutter_close: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Solid {
+        color: Vec3,
+    },
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    /// 经纬度格式的 .hdr 或 .pfm 环境贴图，路径相对于场景文件
+    Environment {
+        path: PathBuf,
+        // 绕 y 轴旋转的角度
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    /// Preetham 解析天空，同时加入一个对应的太阳光源，角度的单位是度
+    PhysicalSky {
+        elevation: f32,
+        #[serde(default)]
+        azimuth: f32,
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    /// PNG、.hdr 或 .pfm 图像，路径相对于场景文件
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+    },
+    /// 三维棋盘格，`scale` 是格子的边长
+    Checker {
+        odd: Vec3,
+        even: Vec3,
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+    // 下面几种 Perlin 噪声纹理用 `seed` 控制随机性
+    Noise {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+    Turbulence {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default = "default_depth")]
+        depth: usize,
+        #[serde(default)]
+        seed: u64,
+    },
+    Marble {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default = "default_depth")]
+        depth: usize,
+        #[serde(default = "default_marble_color")]
+        color: Vec3,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_time1() -> f32 {
+    1.0
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_depth() -> usize {
+    7
+}
+
+fn default_marble_color() -> Vec3 {
+    Vec3(1.0, 1.0, 1.0)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    /// `albedo` 和 `texture` 二选一，`texture` 是 `[textures]` 中的名字
+    Lambertian {
+        albedo: Option<Vec3>,
+        texture: Option<String>,
+    },
+    /// 参与介质的相函数，颜色的写法和 `lambertian` 相同
+    Isotropic {
+        albedo: Option<Vec3>,
+        texture: Option<String>,
+    },
+    Metal {
+        albedo: Vec3,
+        fuzz: f32,
+    },
+    Dielectric {
+        ir: f32,
+    },
+    /// GGX 粗糙导体，`metal` 是预设的金属名字，或者直接给出 `eta` 和 `k`
+    RoughConductor {
+        metal: Option<String>,
+        eta: Option<Vec3>,
+        k: Option<Vec3>,
+        alpha: f32,
+    },
+    /// GGX 粗糙绝缘体
+    RoughDielectric {
+        ir: f32,
+        alpha: f32,
+    },
+    /// Disney 风格的 principled 材质，每个参数可以是数值，也可以是 `[textures]` 中的名字
+    Principled {
+        base_color: Option<ParamDesc<Vec3>>,
+        metallic: Option<ParamDesc<f32>>,
+        roughness: Option<ParamDesc<f32>>,
+        specular: Option<ParamDesc<f32>>,
+        specular_tint: Option<ParamDesc<f32>>,
+        sheen: Option<ParamDesc<f32>>,
+        clearcoat: Option<ParamDesc<f32>>,
+        transmission: Option<ParamDesc<f32>>,
+        ior: Option<ParamDesc<f32>>,
+    },
+    DiffuseLight {
+        emit: Vec3,
+    },
+}
+
+/// 材质参数：常数，或者纹理的名字
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParamDesc<T> {
+    Value(T),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DensityDesc {
+    /// 铺满边界包围盒的网格，`values` 直接给出，或者从 `path` 读取小端序 32 位浮点数
+    /// 按 x、y、z 的顺序存放，x 变化最快
+    Grid {
+        resolution: [usize; 3],
+        values: Option<Vec<f32>>,
+        path: Option<PathBuf>,
+    },
+    /// Perlin 湍流
+    Noise {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default = "default_depth")]
+        depth: usize,
+        #[serde(default)]
+        threshold: f32,
+        #[serde(default = "default_density")]
+        density: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_density() -> f32 {
+    1.0
+}
+
+/// 物体上可选的变换参数，`rotate` 是绕 x、y、z 轴依次旋转的角度
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    // 按行给出的 4x4 矩阵
+    matrix: Option<[[f32; 4]; 4]>,
+    scale: Option<ScaleDesc>,
+    rotate: Option<Vec3>,
+    translate: Option<Vec3>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f32),
+    Axes(Vec3),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+        material: Option<String>,
+    },
+    /// 在 `time0` 到 `time1` 之间从 `center0` 移动到 `center1` 的球
+    MovingSphere {
+        center0: Vec3,
+        center1: Vec3,
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f32,
+        material: Option<String>,
+    },
+    XyRect {
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        k: f32,
+        material: Option<String>,
+    },
+    XzRect {
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: Option<String>,
+    },
+    YzRect {
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: Option<String>,
+    },
+    /// 顶点 `q` 加上两条边 `u`、`v` 组成的平行四边形
+    Quad {
+        q: Vec3,
+        u: Vec3,
+        v: Vec3,
+        material: Option<String>,
+    },
+    /// 由两个对角顶点确定的长方体
+    #[serde(rename = "box")]
+    Cuboid {
+        min: Vec3,
+        max: Vec3,
+        material: Option<String>,
+    },
+    /// 均匀的参与介质，`boundary` 是一个封闭的物体，写法和其他物体相同
+    /// `material` 通常是 `isotropic`，边界省略材质时也使用它
+    ConstantMedium {
+        boundary: toml::Table,
+        density: f32,
+        material: String,
+    },
+    /// 密度不均匀的参与介质，吸收和散射系数乘以 `density` 给出的密度场
+    HeterogeneousMedium {
+        boundary: toml::Table,
+        density: DensityDesc,
+        sigma_a: f32,
+        sigma_s: f32,
+        material: String,
+    },
+    /// OBJ 模型，路径相对于场景文件
+    Mesh { path: PathBuf },
+}
+
+/// 解析光源，`direction` 是光传播的方向，角度的单位都是度
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: Vec3,
+        intensity: Vec3,
+    },
+    /// `cone_angle` 是光锥的半角，`falloff_angle` 是边缘处强度逐渐减弱的宽度
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cone_angle: f32,
+        #[serde(default)]
+        falloff_angle: f32,
+    },
+    /// `irradiance` 是垂直于光线的平面上的照度，`angular_diameter` 为 0 时是理想的平行光
+    Directional {
+        direction: Vec3,
+        irradiance: Vec3,
+        #[serde(default)]
+        angular_diameter: f32,
+    },
+}
+
+/// 把字节偏移换算成从 1 开始的行号和列号
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// 光源的亮度每个分量都必须是有限的非负数
+fn is_radiance(color: Vec3) -> bool {
+    [color.0, color.1, color.2]
+        .iter()
+        .all(|c| c.is_finite() && *c >= 0.0)
+}
+
+/// 读取过的模型，发光的三角形单独拿出来，变换后也能被直接采样
+#[derive(Clone)]
+struct MeshInstance {
+    shapes: Option<Arc<dyn Hittable>>,
+    lights: Vec<Arc<dyn Hittable>>,
+}
+
+struct SceneParser<'a> {
+    path: &'a Path,
+    source: &'a str,
+}
+
+impl SceneParser<'_> {
+    fn error(&self, span: Range<usize>, message: String) -> SceneError {
+        let (line, column) = line_column(self.source, span.start);
+        SceneError::Parse {
+            path: self.path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn check(&self, span: &Range<usize>, ok: bool, message: &str) -> Result<(), SceneError> {
+        if ok {
+            Ok(())
+        } else {
+            Err(self.error(span.clone(), message.to_string()))
+        }
+    }
+
+    fn render(&self, section: &Spanned<RenderSection>) -> Result<RenderSettings, SceneError> {
+        let span = section.span();
+        let section = section.get_ref();
+        let mut render = RenderSettings::default();
+        if let Some(width) = &section.width {
+            let ok = *width.get_ref() >= 2;
+            self.check(&width.span(), ok, "`render.width` must be at least 2")?;
+            render.width = *width.get_ref();
+        }
+        if let Some(aspect_ratio) = &section.aspect_ratio {
+            self.check(
+                &aspect_ratio.span(),
+                *aspect_ratio.get_ref() > 0.0,
+                "`render.aspect_ratio` must be greater than 0",
+            )?;
+            render.aspect_ratio = *aspect_ratio.get_ref();
+        }
+        if let Some(samples) = &section.samples {
+            let ok = *samples.get_ref() >= 1;
+            self.check(&samples.span(), ok, "`render.samples` must be at least 1")?;
+            render.samples_per_pixel = *samples.get_ref();
+        }
+        if let Some(max_depth) = &section.max_depth {
+            self.check(
+                &max_depth.span(),
+                *max_depth.get_ref() >= 1,
+                "`render.max_depth` must be at least 1",
+            )?;
+            render.max_depth = *max_depth.get_ref();
+            // 默认的 rr_depth 不超过 max_depth，超过时轮盘赌本来也不会发生
+            render.rr_depth = render.rr_depth.min(render.max_depth);
+        }
+        if let Some(rr_depth) = &section.rr_depth {
+            self.check(
+                &rr_depth.span(),
+                *rr_depth.get_ref() <= render.max_depth,
+                "`render.rr_depth` must not be greater than `max_depth`",
+            )?;
+            render.rr_depth = *rr_depth.get_ref();
+        }
+        render.validate().map_err(|e| self.error(span, e))?;
+        Ok(render)
+    }
+
+    fn camera(&self, section: &Spanned<CameraSection>) -> Result<CameraSettings, SceneError> {
+        let span = section.span();
+        let section = section.get_ref();
+        let mut camera = CameraSettings::default();
+        camera.look_from = section.look_from.unwrap_or(camera.look_from);
+        camera.look_at = section.look_at.unwrap_or(camera.look_at);
+        camera.vup = section.vup.unwrap_or(camera.vup);
+        if let Some(vfov) = &section.vfov {
+            let value = *vfov.get_ref();
+            self.check(
+                &vfov.span(),
+                value > 0.0 && value < 180.0,
+                "`camera.vfov` must be between 0 and 180 degrees (exclusive)",
+            )?;
+            camera.vfov = value;
+        }
+        if let Some(aperture) = &section.aperture {
+            self.check(
+                &aperture.span(),
+                *aperture.get_ref() >= 0.0,
+                "`camera.aperture` must not be negative",
+            )?;
+            camera.aperture = *aperture.get_ref();
+        }
+        if let Some(focus_dist) = &section.focus_dist {
+            self.check(
+                &focus_dist.span(),
+                *focus_dist.get_ref() > 0.0,
+                "`camera.focus_dist` must be greater than 0",
+            )?;
+            camera.focus_dist = *focus_dist.get_ref();
+        }
+        camera.shutter_open = section.shutter_open.unwrap_or(camera.shutter_open);
+        camera.shutter_close = section.shutter_close.unwrap_or(camera.shutter_close);
+        camera
+            .validate()
+            .map_err(|e| self.error(span, format!("invalid camera: {}", e)))?;
+        Ok(camera)
+    }
+
+    /// 把场景文件中的一个表转换成具体类型，`field` 用来在错误信息中指出是哪一项
+    /// 出错时找出引起错误的值，报告它的位置
+    fn entry<T: DeserializeOwned>(&self, entry: &Entry, field: &str) -> Result<T, SceneError> {
+        let parse = |table: toml::Table| {
+            toml::Value::Table(table)
+                .try_into::<T>()
+                .map_err(|e| e.message().to_string())
+        };
+        let message = match parse(entry.table.clone()) {
+            Ok(value) => return Ok(value),
+            Err(message) => message,
+        };
+        // 去掉引起错误的值以后，同样的错误不会再出现；缺少字段时没有对应的值
+        let key = entry
+            .table
+            .keys()
+            .filter(|_| !message.starts_with("missing field"))
+            .find(|key| {
+                let mut table = entry.table.clone();
+                table.remove(key.as_str());
+                parse(table).err().as_ref() != Some(&message)
+            });
+        Err(match key {
+            Some(key) => self.error(entry.at(key), format!("`{}.{}`: {}", field, key, message)),
+            None => self.error(entry.span.clone(), format!("`{}`: {}", field, message)),
+        })
+    }
+
+    /// 取出物体上的变换参数，依次应用 `matrix`、`scale`、`rotate`、`translate`
+    /// 没有变换时返回 None
+    fn transform(&self, entry: &mut Entry, field: &str) -> Result<Option<Mat4>, SceneError> {
+        let mut keys = Entry::inline(toml::Table::new(), entry.span.clone());
+        for key in ["matrix", "scale", "rotate", "translate"] {
+            if let Some(value) = entry.table.remove(key) {
+                keys.table.insert(key.to_string(), value);
+                keys.spans.insert(key.to_string(), entry.at(key));
+            }
+        }
+        let span = match keys.table.keys().next() {
+            Some(key) => keys.at(key),
+            None => return Ok(None),
+        };
+        let desc: TransformDesc = self.entry(&keys, field)?;
+        let mut matrix = desc.matrix.map_or(Mat4::identity(), Mat4::from_rows);
+        self.check(
+            &keys.at("matrix"),
+            matrix.is_affine(),
+            &format!("`{}.matrix`: the last row must be [0, 0, 0, 1]", field),
+        )?;
+        if let Some(scale) = desc.scale {
+            let factors = match scale {
+                ScaleDesc::Uniform(s) => Vec3(s, s, s),
+                ScaleDesc::Axes(factors) => factors,
+            };
+            matrix = Mat4::scaling(factors) * matrix;
+        }
+        if let Some(rotate) = desc.rotate {
+            matrix = Mat4::rotation_z(rotate.z())
+                * Mat4::rotation_y(rotate.y())
+                * Mat4::rotation_x(rotate.x())
+                * matrix;
+        }
+        if let Some(translate) = desc.translate {
+            matrix = Mat4::translation(translate) * matrix;
+        }
+        self.check(
+            &span,
+            matrix.inverse().is_some(),
+            &format!("`{}`: transform is not invertible", field),
+        )?;
+        Ok(Some(matrix))
+    }
+
+    /// 同一个模型只读取一次，所有带变换的实例共享同一棵 BVH
+    fn instance(
+        &self,
+        meshes: &mut HashMap<PathBuf, MeshInstance>,
+        path: &Path,
+    ) -> Result<MeshInstance, SceneError> {
+        if let Some(mesh) = meshes.get(path) {
+            return Ok(mesh.clone());
+        }
+        let mut triangles = vec![];
+        let mut lights = vec![];
+        for mesh in obj::load_obj(path).map_err(SceneError::Mesh)? {
+            for triangle in TriangleMesh::triangles(&mesh) {
+                if triangle.is_light() {
+                    lights.push(Arc::from(triangle));
+                } else {
+                    triangles.push(triangle);
+                }
+            }
+        }
+        let shapes = if triangles.is_empty() {
+            None
+        } else {
+            Some(Arc::new(BvhNode::new(triangles)) as Arc<dyn Hittable>)
+        };
+        let mesh = MeshInstance { shapes, lights };
+        meshes.insert(path.to_path_buf(), mesh.clone());
+        Ok(mesh)
+    }
+
+    fn background(&self, entry: &Entry) -> Result<Background, SceneError> {
+        Ok(match self.entry(entry, "background")? {
+            BackgroundDesc::Solid { color } => Background::Solid(color),
+            BackgroundDesc::Gradient { bottom, top } => Background::Gradient { bottom, top },
+            BackgroundDesc::Environment {
+                path,
+                rotation,
+                intensity,
+            } => {
+                self.check(
+                    &entry.at("intensity"),
+                    intensity >= 0.0,
+                    "`background.intensity` must not be negative",
+                )?;
+                let directory = self.path.parent().unwrap_or_else(|| Path::new(""));
+                let map = EnvironmentMap::load(&directory.join(path), rotation, intensity)
+                    .map_err(SceneError::Image)?;
+                Background::Environment(map)
+            }
+            BackgroundDesc::PhysicalSky {
+                elevation,
+                azimuth,
+                turbidity,
+                intensity,
+            } => {
+                self.check(
+                    &entry.at("elevation"),
+                    (0.0..=90.0).contains(&elevation),
+                    "`background.elevation` must be between 0 and 90",
+                )?;
+                self.check(
+                    &entry.at("turbidity"),
+                    (2.0..=10.0).contains(&turbidity),
+                    "`background.turbidity` must be between 2 and 10",
+                )?;
+                self.check(
+                    &entry.at("intensity"),
+                    intensity >= 0.0,
+                    "`background.intensity` must not be negative",
+                )?;
+                Background::PhysicalSky(PreethamSky::new(elevation, azimuth, turbidity, intensity))
+            }
+        })
+    }
+
+    fn light(&self, field: &str, entry: &Entry) -> Result<AnalyticLight, SceneError> {
+        let check_color = |color: Vec3, name: &str| {
+            self.check(
+                &entry.at(name),
+                is_radiance(color),
+                &format!("`{}.{}` must be finite and not negative", field, name),
+            )
+        };
+        let check_direction = |direction: Vec3| {
+            self.check(
+                &entry.at("direction"),
+                direction.length_squared() > 0.0,
+                &format!("`{}.direction` must not be zero", field),
+            )
+        };
+        Ok(match self.entry(entry, field)? {
+            LightDesc::Point {
+                position,
+                intensity,
+            } => {
+                check_color(intensity, "intensity")?;
+                AnalyticLight::point(position, intensity)
+            }
+            LightDesc::Spot {
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                falloff_angle,
+            } => {
+                check_color(intensity, "intensity")?;
+                check_direction(direction)?;
+                self.check(
+                    &entry.at("cone_angle"),
+                    cone_angle > 0.0 && cone_angle <= 180.0,
+                    &format!("`{}.cone_angle` must be in (0, 180]", field),
+                )?;
+                self.check(
+                    &entry.at("falloff_angle"),
+                    (0.0..=cone_angle).contains(&falloff_angle),
+                    &format!(
+                        "`{}.falloff_angle` must be between 0 and `cone_angle`",
+                        field
+                    ),
+                )?;
+                AnalyticLight::spot(position, direction, intensity, cone_angle, falloff_angle)
+            }
+            LightDesc::Directional {
+                direction,
+                irradiance,
+                angular_diameter,
+            } => {
+                check_color(irradiance, "irradiance")?;
+                check_direction(direction)?;
+                self.check(
+                    &entry.at("angular_diameter"),
+                    (0.0..180.0).contains(&angular_diameter),
+                    &format!("`{}.angular_diameter` must be in [0, 180)", field),
+                )?;
+                AnalyticLight::directional(direction, irradiance, angular_diameter)
+            }
+        })
+    }
+
+    fn texture(&self, field: &str, entry: &Entry) -> Result<Arc<dyn Texture>, SceneError> {
+        let check_scale = |scale: f32| {
+            self.check(
+                &entry.at("scale"),
+                scale > 0.0,
+                &format!("`{}.scale` must be greater than 0", field),
+            )
+        };
+        let check_depth = |depth: usize| {
+            self.check(
+                &entry.at("depth"),
+                depth >= 1,
+                &format!("`{}.depth` must be at least 1", field),
+            )
+        };
+        Ok(match self.entry(entry, field)? {
+            TextureDesc::Image { path, wrap } => {
+                let directory = self.path.parent().unwrap_or_else(|| Path::new(""));
+                Arc::new(
+                    ImageTexture::load(&directory.join(path), wrap).map_err(SceneError::Image)?,
+                )
+            }
+            TextureDesc::Checker { odd, even, scale } => {
+                check_scale(scale)?;
+                Arc::new(CheckerTexture::from_colors(odd, even, scale))
+            }
+            TextureDesc::Noise { scale, seed } => {
+                check_scale(scale)?;
+                Arc::new(NoiseTexture::new(seed, scale))
+            }
+            TextureDesc::Turbulence { scale, depth, seed } => {
+                check_scale(scale)?;
+                check_depth(depth)?;
+                Arc::new(TurbulenceTexture::new(seed, scale, depth))
+            }
+            TextureDesc::Marble {
+                scale,
+                depth,
+                color,
+                seed,
+            } => {
+                check_scale(scale)?;
+                check_depth(depth)?;
+                Arc::new(MarbleTexture::new(seed, scale, depth, color))
+            }
+        })
+    }
+
+    /// 颜色或者纹理的名字，二者必须给出一个
+    fn albedo(
+        &self,
+        albedo: Option<Vec3>,
+        texture: Option<String>,
+        textures: &BTreeMap<String, Arc<dyn Texture>>,
+        entry: &Entry,
+        field: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match (albedo, texture) {
+            (Some(albedo), None) => Ok(Arc::new(SolidColor::new(albedo))),
+            (None, Some(name)) => self.find_texture(
+                &name,
+                textures,
+                &entry.at("texture"),
+                &format!("{}.texture", field),
+            ),
+            _ => Err(self.error(
+                entry.span.clone(),
+                format!("`{}`: expected exactly one of `albedo` or `texture`", field),
+            )),
+        }
+    }
+
+    /// 标量材质参数，常数时检查范围
+    fn scalar_param(
+        &self,
+        param: Option<ParamDesc<f32>>,
+        range: RangeInclusive<f32>,
+        textures: &BTreeMap<String, Arc<dyn Texture>>,
+        span: &Range<usize>,
+        field: &str,
+    ) -> Result<Option<Arc<dyn Texture>>, SceneError> {
+        Ok(match param {
+            Some(ParamDesc::Value(value)) => {
+                let message = if *range.end() == f32::MAX {
+                    format!("`{}` must be at least {}", field, range.start())
+                } else {
+                    format!(
+                        "`{}` must be between {} and {}",
+                        field,
+                        range.start(),
+                        range.end()
+                    )
+                };
+                self.check(span, range.contains(&value), &message)?;
+                Some(Arc::new(SolidColor::new(Vec3(value, value, value))))
+            }
+            Some(ParamDesc::Texture(name)) => {
+                Some(self.find_texture(&name, textures, span, field)?)
+            }
+            None => None,
+        })
+    }
+
+    /// 按名字查找 `[textures]` 中的纹理
+    fn find_texture(
+        &self,
+        name: &str,
+        textures: &BTreeMap<String, Arc<dyn Texture>>,
+        span: &Range<usize>,
+        field: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        textures.get(name).cloned().ok_or_else(|| {
+            self.error(
+                span.clone(),
+                format!("`{}`: unknown texture `{}`", field, name),
+            )
+        })
+    }
+
+    fn material(
+        &self,
+        field: &str,
+        entry: &Entry,
+        textures: &BTreeMap<String, Arc<dyn Texture>>,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        Ok(match self.entry(entry, field)? {
+            MaterialDesc::Lambertian { albedo, texture } => Arc::new(Lambertian::textured(
+                self.albedo(albedo, texture, textures, entry, field)?,
+            )),
+            MaterialDesc::Isotropic { albedo, texture } => Arc::new(Isotropic::textured(
+                self.albedo(albedo, texture, textures, entry, field)?,
+            )),
+            MaterialDesc::Metal { albedo, fuzz } => {
+                self.check(
+                    &entry.at("fuzz"),
+                    (0.0..=1.0).contains(&fuzz),
+                    &format!("`{}.fuzz` must be between 0 and 1", field),
+                )?;
+                Arc::new(Metal::new(albedo, fuzz))
+            }
+            MaterialDesc::Dielectric { ir } => {
+                self.check(
+                    &entry.at("ir"),
+                    ir > 0.0,
+                    &format!("`{}.ir` must be greater than 0", field),
+                )?;
+                Arc::new(Dielectric::new(ir))
+            }
+            MaterialDesc::RoughConductor {
+                metal,
+                eta,
+                k,
+                alpha,
+            } => {
+                self.check(
+                    &entry.at("alpha"),
+                    alpha >= 0.0,
+                    &format!("`{}.alpha` must not be negative", field),
+                )?;
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(metal), None, None) => conductor_ior(&metal).ok_or_else(|| {
+                        self.error(
+                            entry.at("metal"),
+                            format!(
+                                "`{}.metal`: unknown metal `{}`, expected one of `gold`, `silver`, `copper` or `aluminium`",
+                                field, metal
+                            ),
+                        )
+                    })?,
+                    (None, Some(eta), Some(k)) => (eta, k),
+                    _ => {
+                        return Err(self.error(
+                            entry.span.clone(),
+                            format!(
+                                "`{}`: expected either `metal` or both `eta` and `k`",
+                                field
+                            ),
+                        ))
+                    }
+                };
+                Arc::new(RoughConductor::new(eta, k, alpha))
+            }
+            MaterialDesc::RoughDielectric { ir, alpha } => {
+                self.check(
+                    &entry.at("ir"),
+                    ir > 0.0,
+                    &format!("`{}.ir` must be greater than 0", field),
+                )?;
+                self.check(
+                    &entry.at("alpha"),
+                    alpha >= 0.0,
+                    &format!("`{}.alpha` must not be negative", field),
+                )?;
+                Arc::new(RoughDielectric::new(ir, alpha))
+            }
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                clearcoat,
+                transmission,
+                ior,
+            } => {
+                let base_color = match base_color {
+                    Some(ParamDesc::Value(color)) => Arc::new(SolidColor::new(color)),
+                    Some(ParamDesc::Texture(name)) => {
+                        let span = entry.at("base_color");
+                        self.find_texture(&name, textures, &span, &format!("{}.base_color", field))?
+                    }
+                    None => Arc::new(SolidColor::new(Vec3(0.8, 0.8, 0.8))),
+                };
+                let param = |name: &str, param, range: RangeInclusive<f32>| {
+                    let field = format!("{}.{}", field, name);
+                    self.scalar_param(param, range, textures, &entry.at(name), &field)
+                };
+                let unit = 0.0..=1.0;
+                let positive = 0.0..=f32::MAX;
+                let mut material = Principled::new(base_color);
+                if let Some(metallic) = param("metallic", metallic, unit.clone())? {
+                    material = material.with_metallic(metallic);
+                }
+                if let Some(roughness) = param("roughness", roughness, unit.clone())? {
+                    material = material.with_roughness(roughness);
+                }
+                if let Some(specular) = param("specular", specular, positive.clone())? {
+                    material = material.with_specular(specular);
+                }
+                if let Some(tint) = param("specular_tint", specular_tint, unit.clone())? {
+                    material = material.with_specular_tint(tint);
+                }
+                if let Some(sheen) = param("sheen", sheen, positive.clone())? {
+                    material = material.with_sheen(sheen);
+                }
+                if let Some(clearcoat) = param("clearcoat", clearcoat, positive)? {
+                    material = material.with_clearcoat(clearcoat);
+                }
+                if let Some(transmission) = param("transmission", transmission, unit)? {
+                    material = material.with_transmission(transmission);
+                }
+                if let Some(ior) = param("ior", ior, 1e-3..=f32::MAX)? {
+                    material = material.with_ior(ior);
+                }
+                Arc::new(material)
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                self.check(
+                    &entry.at("emit"),
+                    is_radiance(emit),
+                    &format!("`{}.emit` must be finite and not negative", field),
+                )?;
+                Arc::new(DiffuseLight::new(emit))
+            }
+        })
+    }
+
+    /// 检查矩形每条边的两个坐标，例如 `x0 < x1`
+    fn check_rect(
+        &self,
+        entry: &Entry,
+        field: &str,
+        edges: [(&str, f32, f32); 2],
+    ) -> Result<(), SceneError> {
+        for (axis, lo, hi) in edges {
+            self.check(
+                &entry.at(&format!("{}1", axis)),
+                lo < hi,
+                &format!("`{}.{}0` must be less than `{}1`", field, axis, axis),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn lookup(
+        &self,
+        materials: &BTreeMap<String, Arc<dyn Material>>,
+        name: Option<&str>,
+        entry: &Entry,
+        field: &str,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        let name = name.ok_or_else(|| {
+            self.error(
+                entry.span.clone(),
+                format!("`{}`: missing field `material`", field),
+            )
+        })?;
+        materials.get(name).cloned().ok_or_else(|| {
+            self.error(
+                entry.at("material"),
+                format!("`{}.material`: unknown material `{}`", field, name),
+            )
+        })
+    }
+
+    /// 参与介质的边界，省略材质时使用介质的材质
+    fn boundary(
+        &self,
+        table: toml::Table,
+        material: &str,
+        span: Range<usize>,
+        field: &str,
+        materials: &BTreeMap<String, Arc<dyn Material>>,
+        meshes: &mut HashMap<PathBuf, MeshInstance>,
+    ) -> Result<Box<dyn Hittable>, SceneError> {
+        let field = format!("{}.boundary", field);
+        let entry = Entry::inline(table, span);
+        let mut parts = self.object(entry, &field, Some(material), materials, meshes)?;
+        Ok(if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            Box::new(HittableList { objects: parts })
+        })
+    }
+
+    /// 网格密度铺满边界的包围盒
+    fn density(
+        &self,
+        desc: DensityDesc,
+        bounds: &Aabb,
+        span: &Range<usize>,
+        field: &str,
+    ) -> Result<Arc<dyn DensityField>, SceneError> {
+        Ok(match desc {
+            DensityDesc::Grid {
+                resolution,
+                values,
+                path,
+            } => {
+                self.check(
+                    span,
+                    resolution.iter().all(|&n| n >= 1),
+                    &format!(
+                        "`{}.density.resolution` must be at least 1 on every axis",
+                        field
+                    ),
+                )?;
+                let values = match (values, path) {
+                    (Some(values), None) => values,
+                    (None, Some(path)) => {
+                        let directory = self.path.parent().unwrap_or_else(|| Path::new(""));
+                        let path = directory.join(path);
+                        let data = std::fs::read(&path)
+                            .map_err(|source| SceneError::Io { path, source })?;
+                        data.chunks_exact(4)
+                            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                            .collect()
+                    }
+                    _ => {
+                        return Err(self.error(
+                            span.clone(),
+                            format!(
+                                "`{}.density`: expected exactly one of `values` or `path`",
+                                field
+                            ),
+                        ))
+                    }
+                };
+                let count = resolution[0] * resolution[1] * resolution[2];
+                self.check(
+                    span,
+                    values.len() == count,
+                    &format!(
+                        "`{}.density`: expected {} values for resolution {:?}, got {}",
+                        field,
+                        count,
+                        resolution,
+                        values.len()
+                    ),
+                )?;
+                self.check(
+                    span,
+                    values.iter().all(|&v| v >= 0.0 && v.is_finite()),
+                    &format!(
+                        "`{}.density`: values must be finite and not negative",
+                        field
+                    ),
+                )?;
+                Arc::new(GridDensity::new(*bounds, resolution, values))
+            }
+            DensityDesc::Noise {
+                scale,
+                depth,
+                threshold,
+                density,
+                seed,
+            } => {
+                self.check(
+                    span,
+                    scale > 0.0 && depth >= 1 && density >= 0.0,
+                    &format!(
+                        "`{}.density`: `scale` must be greater than 0, `depth` at least 1 and `density` not negative",
+                        field
+                    ),
+                )?;
+                self.check(
+                    span,
+                    (0.0..1.0).contains(&threshold),
+                    &format!("`{}.density.threshold` must be in [0, 1)", field),
+                )?;
+                Arc::new(NoiseDensity::new(seed, scale, depth, threshold, density))
+            }
+        })
+    }
+
+    /// 构建一个物体，带变换时每个形状都包上 `Transform`
+    /// 形状省略 `material` 时使用 `default_material`，参与介质的边界用它传入介质的材质
+    fn object(
+        &self,
+        mut entry: Entry,
+        field: &str,
+        default_material: Option<&str>,
+        materials: &BTreeMap<String, Arc<dyn Material>>,
+        meshes: &mut HashMap<PathBuf, MeshInstance>,
+    ) -> Result<Vec<Box<dyn Hittable>>, SceneError> {
+        let directory = self.path.parent().unwrap_or_else(|| Path::new(""));
+        let transform = self.transform(&mut entry, field)?;
+        let mut shapes = HittableList::new();
+        let lookup = |material: Option<&str>| {
+            self.lookup(materials, material.or(default_material), &entry, field)
+        };
+        match self.entry(&entry, field)? {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                self.check(
+                    &entry.at("radius"),
+                    radius > 0.0,
+                    &format!("`{}.radius` must be greater than 0", field),
+                )?;
+                shapes.add(Box::new(Sphere {
+                    center,
+                    radius,
+                    material: lookup(material.as_deref())?,
+                }));
+            }
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                self.check(
+                    &entry.at("radius"),
+                    radius > 0.0,
+                    &format!("`{}.radius` must be greater than 0", field),
+                )?;
+                self.check(
+                    &entry.at("time1"),
+                    time1 > time0,
+                    &format!("`{}.time1` must be later than `time0`", field),
+                )?;
+                shapes.add(Box::new(MovingSphere {
+                    center0,
+                    center1,
+                    time0,
+                    time1,
+                    radius,
+                    material: lookup(material.as_deref())?,
+                }));
+            }
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => {
+                self.check_rect(&entry, field, [("x", x0, x1), ("y", y0, y1)])?;
+                shapes.add(Box::new(XyRect {
+                    x0,
+                    x1,
+                    y0,
+                    y1,
+                    k,
+                    material: lookup(material.as_deref())?,
+                }));
+            }
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                self.check_rect(&entry, field, [("x", x0, x1), ("z", z0, z1)])?;
+                shapes.add(Box::new(XzRect {
+                    x0,
+                    x1,
+                    z0,
+                    z1,
+                    k,
+                    material: lookup(material.as_deref())?,
+                }));
+            }
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                self.check_rect(&entry, field, [("y", y0, y1), ("z", z0, z1)])?;
+                shapes.add(Box::new(YzRect {
+                    y0,
+                    y1,
+                    z0,
+                    z1,
+                    k,
+                    material: lookup(material.as_deref())?,
+                }));
+            }
+            ObjectDesc::Quad { q, u, v, material } => {
+                self.check(
+                    &entry.at("v"),
+                    !u.cross(v).near_zero(),
+                    &format!("`{}.u` and `{}.v` must not be parallel", field, field),
+                )?;
+                let material = lookup(material.as_deref())?;
+                shapes.add(Box::new(Quad::new(q, u, v, material)));
+            }
+            ObjectDesc::Cuboid { min, max, material } => {
+                let size = max - min;
+                self.check(
+                    &entry.at("max"),
+                    size.x() > 0.0 && size.y() > 0.0 && size.z() > 0.0,
+                    &format!("`{}.max` must be greater than `min` on every axis", field),
+                )?;
+                let material = lookup(material.as_deref())?;
+                shapes.add(Box::new(Cuboid::new(min, max, material)));
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                material,
+            } => {
+                self.check(
+                    &entry.at("density"),
+                    density > 0.0,
+                    &format!("`{}.density` must be greater than 0", field),
+                )?;
+                let phase_function = lookup(Some(&material))?;
+                let span = entry.at("boundary");
+                let boundary =
+                    self.boundary(boundary, &material, span, field, materials, meshes)?;
+                shapes.add(Box::new(ConstantMedium::with_phase_function(
+                    boundary,
+                    density,
+                    phase_function,
+                )));
+            }
+            ObjectDesc::HeterogeneousMedium {
+                boundary,
+                density,
+                sigma_a,
+                sigma_s,
+                material,
+            } => {
+                self.check(
+                    &entry.at("sigma_a"),
+                    sigma_a >= 0.0,
+                    &format!("`{}.sigma_a` must not be negative", field),
+                )?;
+                self.check(
+                    &entry.at("sigma_s"),
+                    sigma_s >= 0.0,
+                    &format!("`{}.sigma_s` must not be negative", field),
+                )?;
+                let phase_function = lookup(Some(&material))?;
+                let span = entry.at("boundary");
+                let boundary =
+                    self.boundary(boundary, &material, span, field, materials, meshes)?;
+                let density =
+                    self.density(density, &boundary.aabb(), &entry.at("density"), field)?;
+                shapes.add(Box::new(HeterogeneousMedium::new(
+                    boundary,
+                    density,
+                    sigma_a,
+                    sigma_s,
+                    phase_function,
+                )));
+            }
+            ObjectDesc::Mesh { path } => {
+                let path = directory.join(path);
+                if let Some(matrix) = transform {
+                    let mesh = self.instance(meshes, &path)?;
+                    return Ok(mesh
+                        .shapes
+                        .into_iter()
+                        .chain(mesh.lights)
+                        .map(|shape| -> Box<dyn Hittable> {
+                            Box::new(Transform::with_matrix(shape, matrix).unwrap())
+                        })
+                        .collect());
+                }
+                for mesh in obj::load_obj(&path).map_err(SceneError::Mesh)? {
+                    shapes.objects.extend(TriangleMesh::triangles(&mesh));
+                }
+            }
+        }
+        Ok(match transform {
+            None => shapes.objects,
+            Some(matrix) => shapes
+                .objects
+                .into_iter()
+                .map(|shape| -> Box<dyn Hittable> {
+                    Box::new(Transform::with_matrix(Arc::from(shape), matrix).unwrap())
+                })
+                .collect(),
+        })
+    }
+
+    fn parse(&self) -> Result<Scene, SceneError> {
+        let file: SceneFile = toml::from_str(self.source)
+            .map_err(|e| self.error(e.span().unwrap_or(0..0), e.message().to_string()))?;
+
+        let render = match &file.render {
+            Some(section) => self.render(section)?,
+            None => RenderSettings::default(),
+        };
+        let camera = match &file.camera {
+            Some(section) => self.camera(section)?,
+            None => CameraSettings::default(),
+        };
+        let background = match &file.background {
+            Some(table) => self.background(&Entry::new(table))?,
+            None => Background::default(),
+        };
+
+        let mut textures = BTreeMap::new();
+        for (name, table) in &file.textures {
+            let texture = self.texture(&format!("textures.{}", name), &Entry::new(table))?;
+            textures.insert(name.clone(), texture);
+        }
+
+        let mut materials = BTreeMap::new();
+        for (name, table) in &file.materials {
+            let entry = Entry::new(table);
+            let material = self.material(&format!("materials.{}", name), &entry, &textures)?;
+            materials.insert(name.clone(), material);
+        }
+
+        let mut world = HittableList::new();
+        let mut meshes = HashMap::new();
+        for (index, object) in file.objects.iter().enumerate() {
+            let field = format!("objects[{}]", index);
+            let shapes = self.object(Entry::new(object), &field, None, &materials, &mut meshes)?;
+            world.objects.extend(shapes);
+        }
+
+        let mut lights = vec![];
+        for (index, table) in file.lights.iter().enumerate() {
+            lights.push(self.light(&format!("lights[{}]", index), &Entry::new(table))?);
+        }
+
+        Ok(Scene {
+            world,
+            camera,
+            render,
+            background,
+            lights,
+        })
+    }
+}
+
+/// 读取 TOML 格式的场景文件
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let source = std::fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    SceneParser {
+        path,
+        source: &source,
+    }
+    .parse()
+}
+
+/// 书中最终的随机小球场景
+pub fn random_scene() -> HittableList {
+    let mut world = HittableList::new();
+    let checker = CheckerTexture::from_colors(Vec3(0.2, 0.3, 0.1), Vec3(0.9, 0.9, 0.9), 0.32);
+    let ground_material = Arc::new(Lambertian::textured(Arc::new(checker)));
+    world.add(Box::new(Sphere {
+        center: Vec3(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        material: ground_material,
+    }));
+
+    let random_double_in_range = utils::random_range;
+    let random_double = utils::random_f32;
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = random_double();
+            let center = Vec3(
+                a as f32 + 0.9 * random_double(),
+                0.2,
+                b as f32 + 0.9 * random_double(),
+            );
+            if (center - Vec3(4.0, 0.2, 0.0)).length() > 0.9 {
+                let sphere_material: Arc<dyn Material>;
+                if choose_mat < 0.8 {
+                    // diffuse
+                    let albedo = Vec3::random_color() * Vec3::random_color();
+                    sphere_material = Arc::new(Lambertian::new(albedo));
+                } else if choose_mat < 0.95 {
+                    // metal
+                    let albedo = Vec3::random_color_in_range(0.5, 1.0);
+                    let fuzz = random_double_in_range(0.0, 0.5);
+                    sphere_material = Arc::new(Metal::new(albedo, fuzz));
+                } else {
+                    // glass
+                    sphere_material = Arc::new(Dielectric::new(1.5));
+                }
+                world.add(Box::new(Sphere {
+                    center,
+                    radius: 0.2,
+                    material: sphere_material,
+                }));
+            }
+        }
+    }
+
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Box::new(Sphere {
+        center: Vec3(0.0, 1.0, 0.0),
+        radius: 1.0,
+        material: material1,
+    }));
+
+    let material2 = Arc::new(Lambertian::new(Vec3(0.4, 0.2, 0.1)));
+    world.add(Box::new(Sphere {
+        center: Vec3(-4.0, 1.0, 0.0),
+        radius: 1.0,
+        material: material2,
+    }));
+
+    let material3 = Arc::new(Metal::new(Vec3(0.7, 0.6, 0.5), 0.0));
+    world.add(Box::new(Sphere {
+        center: Vec3(4.0, 1.0, 0.0),
+        radius: 1.0,
+        material: material3,
+    }));
+
+    world
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::LightList;
+    use crate::ray::Ray;
+
+    /// 解析场景文本，返回错误的行、列和信息
+    fn parse_error(source: &str) -> (usize, usize, String) {
+        let parser = SceneParser {
+            path: Path::new("test.toml"),
+            source,
+        };
+        match parser.parse() {
+            Err(SceneError::Parse {
+                line,
+                column,
+                message,
+                ..
+            }) => (line, column, message),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("scene should not load:\n{}", source),
+        }
+    }
+
+    const MATERIALS: &str = "[materials.white]\ntype = \"lambertian\"\nalbedo = [0.8, 0.8, 0.8]\n";
+
+    #[test]
+    fn errors_point_at_the_offending_value() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\n",
+            MATERIALS
+        ));
+        assert_eq!((line, column), (9, 12));
+        assert_eq!(message, "`objects[0].material`: unknown material `red`");
+
+        let (line, column, message) = parse_error(&format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = -2.5\nmaterial = \"white\"\n",
+            MATERIALS
+        ));
+        assert_eq!((line, column), (8, 10));
+        assert_eq!(message, "`objects[0].radius` must be greater than 0");
+
+        let (line, column, message) = parse_error(&format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = \"big\"\nmaterial = \"white\"\n",
+            MATERIALS
+        ));
+        assert_eq!((line, column), (8, 10));
+        assert!(
+            message.starts_with("`objects[0].radius`: invalid type: string \"big\""),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn unknown_types_and_fields_report_their_key() {
+        let (line, column, message) = parse_error(&format!(
+            "{}\n[[objects]]\ntype = \"torus\"\nmaterial = \"white\"\n",
+            MATERIALS
+        ));
+        assert_eq!((line, column), (6, 8));
+        assert!(
+            message.starts_with("`objects[0].type`: unknown variant `torus`"),
+            "{}",
+            message
+        );
+
+        let (line, column, message) =
+            parse_error("[materials.glass]\ntype = \"dielectric\"\nir = 1.5\ncolor = [1, 1, 1]\n");
+        assert_eq!((line, column), (4, 9));
+        assert!(
+            message.starts_with("`materials.glass.color`: unknown field `color`"),
+            "{}",
+            message
+        );
+
+        let (line, column, message) = parse_error(&format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"white\"\n\
+             matrix = [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 1, 1]]\n",
+            MATERIALS
+        ));
+        assert_eq!((line, column), (10, 10));
+        assert_eq!(
+            message,
+            "`objects[0].matrix`: the last row must be [0, 0, 0, 1]"
+        );
+
+        let (line, column, message) = parse_error("[render]\nmax_depth = 4\nrr_depth = 5\n");
+        assert_eq!((line, column), (3, 12));
+        assert_eq!(
+            message,
+            "`render.rr_depth` must not be greater than `max_depth`"
+        );
+
+        let (line, column, message) =
+            parse_error("[materials.glass]\ntype = \"dielectric\"\nir = 0\n");
+        assert_eq!((line, column), (3, 6));
+        assert_eq!(message, "`materials.glass.ir` must be greater than 0");
+    }
+
+    #[test]
+    fn light_brightness_must_be_finite_and_not_negative() {
+        let (line, column, message) =
+            parse_error("[materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, -1, 4]\n");
+        assert_eq!((line, column), (3, 8));
+        assert_eq!(
+            message,
+            "`materials.lamp.emit` must be finite and not negative"
+        );
+
+        let (line, column, message) =
+            parse_error("[materials.lamp]\ntype = \"diffuse_light\"\nemit = [inf, 4, 4]\n");
+        assert_eq!((line, column), (3, 8));
+        assert_eq!(
+            message,
+            "`materials.lamp.emit` must be finite and not negative"
+        );
+
+        let (line, column, message) = parse_error(
+            "[[lights]]\ntype = \"point\"\nposition = [0, 1, 0]\nintensity = [1, nan, 1]\n",
+        );
+        assert_eq!((line, column), (4, 13));
+        assert_eq!(
+            message,
+            "`lights[0].intensity` must be finite and not negative"
+        );
+
+        let (line, column, message) = parse_error(
+            "[[lights]]\ntype = \"spot\"\nposition = [0, 1, 0]\ndirection = [0, -1, 0]\n\
+             intensity = [-5, 5, 5]\ncone_angle = 30\nfalloff_angle = 5\n",
+        );
+        assert_eq!((line, column), (5, 13));
+        assert_eq!(
+            message,
+            "`lights[0].intensity` must be finite and not negative"
+        );
+    }
+
+    /// 边长为 2、中心在原点的立方体
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+f 4 7 3\nf 4 8 7\nf 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+
+    #[test]
+    fn media_accept_mesh_boundaries() {
+        let directory =
+            std::env::temp_dir().join(format!("raytrace-scene-{}-media", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("cube.obj"), CUBE).unwrap();
+        let path = directory.join("media.toml");
+        std::fs::write(
+            &path,
+            "[materials.smoke]\ntype = \"isotropic\"\nalbedo = [1, 1, 1]\n\n\
+             [[objects]]\ntype = \"constant_medium\"\ndensity = 1000\nmaterial = \"smoke\"\n\
+             boundary = { type = \"mesh\", path = \"cube.obj\", translate = [0, 0, -5] }\n\n\
+             [[objects]]\ntype = \"heterogeneous_medium\"\nsigma_a = 0\nsigma_s = 1000\n\
+             material = \"smoke\"\nboundary = { type = \"mesh\", path = \"cube.obj\" }\n\
+             density = { type = \"grid\", resolution = [1, 1, 1], values = [1] }\n",
+        )
+        .unwrap();
+        let scene = load_scene(&path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let scene = scene.unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(scene.world.objects.len(), 2);
+
+        // 两个介质都很浓，光线进入边界后马上就会散射
+        let ray = Ray::new(Vec3(0.0, 0.0, 5.0), Vec3(0.0, 0.0, -1.0));
+        let hit = scene.world.objects[1].hit(0.001, f32::MAX, &ray).unwrap();
+        assert!((4.0..4.1).contains(&hit.t), "{}", hit.t);
+        let hit = scene.world.objects[0].hit(0.001, f32::MAX, &ray).unwrap();
+        assert!((9.0..9.1).contains(&hit.t), "{}", hit.t);
+    }
+
+    #[test]
+    fn transformed_emissive_meshes_are_sampled_lights() {
+        let directory =
+            std::env::temp_dir().join(format!("raytrace-scene-{}-lamp", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // 只有顶面 (y = 1) 发光
+        let (faces, top) = CUBE.split_at(CUBE.find("f 4 7 3").unwrap());
+        let (top, rest) = top.split_at(top.find("f 1 5 8").unwrap());
+        let obj = format!(
+            "mtllib lamp.mtl\nusemtl grey\n{}usemtl lamp\n{}usemtl grey\n{}",
+            faces, top, rest
+        );
+        std::fs::write(directory.join("lamp.obj"), obj).unwrap();
+        std::fs::write(
+            directory.join("lamp.mtl"),
+            "newmtl grey\nKd 0.5 0.5 0.5\nnewmtl lamp\nKe 4 4 4\n",
+        )
+        .unwrap();
+        let path = directory.join("lamp.toml");
+        std::fs::write(
+            &path,
+            "[[objects]]\ntype = \"mesh\"\npath = \"lamp.obj\"\nscale = 2\ntranslate = [0, 5, 0]\n",
+        )
+        .unwrap();
+        let scene = load_scene(&path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let scene = scene.unwrap_or_else(|e| panic!("{}", e));
+
+        // 不发光的三角形留在 BVH 里，两个发光的三角形各自带上变换
+        assert_eq!(scene.world.objects.len(), 3);
+        let (objects, lights) = LightList::extract(scene.world.objects);
+        assert_eq!(objects.len(), 3);
+        assert_eq!(lights.len(), 2);
+
+        // 从正下方采样，光源在变换后的顶面 y = 7 上
+        let origin = Vec3(0.0, 0.0, 0.0);
+        for _ in 0..100 {
+            let sample = lights.sample(origin, 0.0).unwrap();
+            let p = origin + sample.distance * sample.direction;
+            assert!((p.y() - 7.0).abs() < 1e-3, "{:?}", p);
+            assert!(p.x().abs() <= 2.0 + 1e-3 && p.z().abs() <= 2.0 + 1e-3);
+            assert!(sample.pdf > 0.0);
+        }
+    }
+}
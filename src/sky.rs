@@ -0,0 +1,203 @@
+use crate::light::AnalyticLight;
+use crate::utils::degrees_to_radians;
+use crate::vec::Vec3;
+use std::f32::consts::PI;
+
+/// 太阳的视直径，单位是度
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.53;
+
+// 亮度以 20 kcd/m² 为 1，晴天正午被太阳照亮的白色表面亮度大约是 1
+const LUMINANCE_UNIT: f32 = 1.0 / 20.0;
+// 大气层外的太阳亮度，单位是 kcd/m²
+const SOLAR_LUMINANCE: f32 = 1.9e6;
+// 计算太阳透射率时 R、G、B 三个通道使用的波长，单位是微米
+const WAVELENGTHS: [f32; 3] = [0.65, 0.57, 0.475];
+
+/// Preetham 等人 (1999) 的解析日光模型
+/// 天空的亮度和色度由 Perez 公式给出，参数只和太阳的位置与大气浑浊度有关。
+/// 太阳本身用 `sun` 给出的平行光表示，地平线以下使用地平线处的颜色。
+#[derive(Clone, Debug)]
+pub struct PreethamSky {
+    // 指向太阳的单位向量
+    sun_direction: Vec3,
+    turbidity: f32,
+    // 天顶处的亮度 Y (kcd/m²) 和色度 x、y
+    zenith: [f32; 3],
+    // Y、x、y 三个分量的 Perez 系数 A 到 E
+    perez: [[f32; 5]; 3],
+    // 亮度倍数
+    pub intensity: f32,
+}
+
+impl PreethamSky {
+    /// `elevation` 是太阳的高度角，`azimuth` 是方位角，0 度在 -z 方向、90 度在 +x 方向，
+    /// 和环境贴图的约定相同。`turbidity` 是大气浑浊度，晴朗的天空约为 2，有雾时接近 10
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) -> PreethamSky {
+        let elevation = degrees_to_radians(elevation);
+        let azimuth = degrees_to_radians(azimuth);
+        let sun_direction = Vec3(
+            libm::cosf(elevation) * libm::sinf(azimuth),
+            libm::sinf(elevation),
+            -libm::cosf(elevation) * libm::cosf(azimuth),
+        );
+        let t = turbidity;
+        let theta_s = PI / 2.0 - elevation;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * libm::tanf(chi) - 0.2155 * t + 2.4192;
+        let chromaticity = |c: [[f32; 4]; 3]| {
+            let theta = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+            let row = |r: [f32; 4]| r.iter().zip(theta).map(|(a, b)| a * b).sum::<f32>();
+            t * t * row(c[0]) + t * row(c[1]) + row(c[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_yc = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        PreethamSky {
+            sun_direction,
+            turbidity,
+            zenith: [zenith_y, zenith_x, zenith_yc],
+            perez,
+            intensity,
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    /// 沿 `direction` 看到的天空的辐射亮度，不包括太阳
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let d = direction.to_unit_vector();
+        let cos_theta = d.y().max(1e-3);
+        let cos_gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = libm::acosf(cos_gamma);
+        let cos_theta_s = self.sun_direction.y().max(1e-3);
+        let theta_s = libm::acosf(cos_theta_s);
+
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let f = perez(&self.perez[i], cos_theta, gamma, cos_gamma);
+            let f0 = perez(&self.perez[i], 1.0, theta_s, cos_theta_s);
+            self.zenith[i] * f / f0
+        });
+        self.intensity * LUMINANCE_UNIT * xyy_to_rgb(x, y, luminance)
+    }
+
+    /// 穿过大气衰减后的太阳，作为一个有视直径的平行光
+    pub fn sun(&self) -> AnalyticLight {
+        let elevation = libm::asinf(self.sun_direction.y().clamp(0.0, 1.0));
+        let zenith_angle = 90.0 - elevation.to_degrees();
+        // 大气的相对光学质量，Kasten 的近似公式
+        let mass = 1.0
+            / (libm::cosf(degrees_to_radians(zenith_angle))
+                + 0.15 * libm::powf(93.885 - zenith_angle, -1.253));
+        // Ångström 公式中的气溶胶浑浊系数
+        let beta = 0.04608365 * self.turbidity - 0.04586026;
+        let [r, g, b] = WAVELENGTHS.map(|lambda| {
+            let rayleigh = 0.008735 * libm::powf(lambda, -4.08);
+            let aerosol = beta * libm::powf(lambda, -1.3);
+            libm::expf(-mass * (rayleigh + aerosol))
+        });
+        let radiance = self.intensity * LUMINANCE_UNIT * SOLAR_LUMINANCE * Vec3(r, g, b);
+        let sin_radius = libm::sinf(degrees_to_radians(0.5 * SUN_ANGULAR_DIAMETER));
+        AnalyticLight::directional(
+            -self.sun_direction,
+            PI * sin_radius * sin_radius * radiance,
+            SUN_ANGULAR_DIAMETER,
+        )
+    }
+}
+
+/// Perez 等人的天空亮度分布公式
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32) -> f32 {
+    (1.0 + c[0] * libm::expf(c[1] / cos_theta))
+        * (1.0 + c[2] * libm::expf(c[3] * gamma) + c[4] * cos_gamma * cos_gamma)
+}
+
+/// CIE xyY 转换成线性 sRGB，超出色域的分量截断为 0
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    Vec3(
+        (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_matches_zenith_values_and_sun_reddens_near_horizon() {
+        let sky = PreethamSky::new(40.0, 30.0, 3.0, 1.0);
+        // 天顶处的 Perez 公式化简为天顶的亮度
+        let zenith = sky.radiance(Vec3(0.0, 1.0, 0.0));
+        let expected = LUMINANCE_UNIT * sky.zenith[0];
+        assert!(
+            (zenith.luminance() - expected).abs() < 0.01 * expected,
+            "zenith {:?} expected luminance {}",
+            zenith,
+            expected
+        );
+        assert!(
+            zenith.2 > zenith.0,
+            "clear sky should be blue: {:?}",
+            zenith
+        );
+        // 太阳附近比背对太阳的方向亮
+        let towards = sky.radiance(sky.sun_direction() + Vec3(0.0, 0.2, 0.0));
+        let away = sky.radiance(Vec3(
+            -sky.sun_direction().x(),
+            0.5,
+            -sky.sun_direction().z(),
+        ));
+        assert!(towards.luminance() > away.luminance());
+
+        let irradiance = |elevation: f32| {
+            let sun = PreethamSky::new(elevation, 0.0, 3.0, 1.0).sun();
+            match sun {
+                AnalyticLight::Directional { irradiance, .. } => irradiance,
+                _ => unreachable!(),
+            }
+        };
+        let (high, low) = (irradiance(60.0), irradiance(5.0));
+        assert!(high.luminance() > low.luminance());
+        assert!(low.0 / low.2 > high.0 / high.2, "{:?} {:?}", high, low);
+    }
+}
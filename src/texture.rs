@@ -0,0 +1,362 @@
+use crate::hdr::{self, HdrError, HdrImage};
+use crate::perlin::Perlin;
+use crate::tonemap::srgb_to_linear;
+use crate::vec::Vec3;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+/// 纹理根据表面坐标 (u, v) 和击中点 p 给出颜色
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3;
+}
+
+/// 纯色
+pub struct SolidColor {
+    pub color: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// 三维棋盘格，边长为 `scale` 的立方体交替使用两种纹理
+pub struct CheckerTexture {
+    pub odd: Arc<dyn Texture>,
+    pub even: Arc<dyn Texture>,
+    pub scale: f32,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f32) -> CheckerTexture {
+        CheckerTexture { odd, even, scale }
+    }
+
+    pub fn from_colors(odd: Vec3, even: Vec3, scale: f32) -> CheckerTexture {
+        CheckerTexture::new(
+            Arc::new(SolidColor::new(odd)),
+            Arc::new(SolidColor::new(even)),
+            scale,
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let cell = |x: f32| libm::floorf(x / self.scale) as i64;
+        if (cell(p.x()) + cell(p.y()) + cell(p.z())).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// 平滑的 Perlin 噪声，把 [-1, 1] 映射到 [0, 1] 的灰度
+pub struct NoiseTexture {
+    noise: Perlin,
+    // 频率
+    pub scale: f32,
+}
+
+impl NoiseTexture {
+    pub fn new(seed: u64, scale: f32) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::new(seed),
+            scale,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let n = 0.5 * (1.0 + self.noise.noise(self.scale * p));
+        Vec3(n, n, n)
+    }
+}
+
+/// 多层噪声叠加的湍流，`depth` 是叠加的层数
+pub struct TurbulenceTexture {
+    noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+}
+
+impl TurbulenceTexture {
+    pub fn new(seed: u64, scale: f32, depth: usize) -> TurbulenceTexture {
+        TurbulenceTexture {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+        }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let t = self.noise.turbulence(self.scale * p, self.depth).min(1.0);
+        Vec3(t, t, t)
+    }
+}
+
+/// 大理石：沿 z 方向的正弦条纹，用湍流扰动相位
+pub struct MarbleTexture {
+    noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+    pub color: Vec3,
+}
+
+impl MarbleTexture {
+    pub fn new(seed: u64, scale: f32, depth: usize, color: Vec3) -> MarbleTexture {
+        MarbleTexture {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+            color,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let phase = self.scale * p.z() + 10.0 * self.noise.turbulence(p, self.depth);
+        0.5 * (1.0 + libm::sinf(phase)) * self.color
+    }
+}
+
+/// 纹理坐标超出 [0, 1] 时的处理方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    /// 平铺
+    #[default]
+    Repeat,
+    /// 使用边缘的像素
+    Clamp,
+    /// 镜像平铺
+    Mirror,
+}
+
+impl WrapMode {
+    /// 把可能越界的像素下标映射到 [0, size) 内
+    fn apply(self, i: i64, size: usize) -> usize {
+        let n = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// 图像纹理，像素保存为线性颜色，使用双线性过滤
+/// v = 0 对应图像的最下面一行
+pub struct ImageTexture {
+    image: HdrImage,
+    pub wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: HdrImage, wrap: WrapMode) -> ImageTexture {
+        ImageTexture { image, wrap }
+    }
+
+    /// 读取 PNG（按 sRGB 解码成线性颜色），或者 .hdr / .pfm 图像
+    pub fn load(path: &Path, wrap: WrapMode) -> Result<ImageTexture, HdrError> {
+        let is_png = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+        let image = if is_png {
+            load_png(path)?
+        } else {
+            hdr::load(path)?
+        };
+        Ok(ImageTexture::new(image, wrap))
+    }
+
+    pub fn image(&self) -> &HdrImage {
+        &self.image
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: Vec3) -> Vec3 {
+        let image = &self.image;
+        let x = u * image.width as f32 - 0.5;
+        let y = (1.0 - v) * image.height as f32 - 0.5;
+        if !x.is_finite() || !y.is_finite() {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        let x0 = libm::floorf(x);
+        let y0 = libm::floorf(y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let (x1, y1) = (
+            self.wrap.apply(x0 + 1, image.width),
+            self.wrap.apply(y0 + 1, image.height),
+        );
+        let (x0, y0) = (
+            self.wrap.apply(x0, image.width),
+            self.wrap.apply(y0, image.height),
+        );
+        let top = (1.0 - fx) * image.get(x0, y0) + fx * image.get(x1, y0);
+        let bottom = (1.0 - fx) * image.get(x0, y1) + fx * image.get(x1, y1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+fn load_png(path: &Path) -> Result<HdrImage, HdrError> {
+    let data = std::fs::read(path).map_err(|source| HdrError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let bitmap = lodepng::decode24(&data).map_err(|e| HdrError::Format {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    let pixels = bitmap
+        .buffer
+        .iter()
+        .map(|p| {
+            let linear = |c: u8| srgb_to_linear(c as f32 / 255.0);
+            Vec3(linear(p.r), linear(p.g), linear(p.b))
+        })
+        .collect();
+    Ok(HdrImage {
+        width: bitmap.width,
+        height: bitmap.height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 灰度图像，`rows` 从上到下
+    fn gray_image(rows: &[&[f32]], wrap: WrapMode) -> ImageTexture {
+        let pixels = rows
+            .iter()
+            .flat_map(|row| row.iter().map(|&x| Vec3(x, x, x)))
+            .collect();
+        let image = HdrImage {
+            width: rows[0].len(),
+            height: rows.len(),
+            pixels,
+        };
+        ImageTexture::new(image, wrap)
+    }
+
+    fn sample(texture: &ImageTexture, u: f32, v: f32) -> f32 {
+        texture.value(u, v, Vec3(0.0, 0.0, 0.0)).0
+    }
+
+    #[test]
+    fn checker_alternates_between_cells() {
+        let checker = CheckerTexture::from_colors(Vec3(1.0, 1.0, 1.0), Vec3(0.0, 0.0, 0.0), 2.0);
+        let odd = |p: Vec3| checker.value(0.0, 0.0, p).0 == 1.0;
+        assert!(!odd(Vec3(0.5, 1.5, 1.9)));
+        assert!(odd(Vec3(2.5, 0.5, 0.5)));
+        assert!(odd(Vec3(0.5, 0.5, -0.5)));
+        assert!(!odd(Vec3(-0.5, -0.5, 0.5)));
+        assert!(!odd(Vec3(-2.5, 0.5, 0.5)));
+        assert!(odd(Vec3(-2.5, -0.5, 0.5)));
+    }
+
+    #[test]
+    fn noise_textures_depend_only_on_the_seed() {
+        let p = Vec3(0.3, 1.7, -2.2);
+        let textures: [(Box<dyn Texture>, Box<dyn Texture>); 3] = [
+            (
+                Box::new(NoiseTexture::new(3, 4.0)),
+                Box::new(NoiseTexture::new(3, 4.0)),
+            ),
+            (
+                Box::new(TurbulenceTexture::new(3, 4.0, 7)),
+                Box::new(TurbulenceTexture::new(3, 4.0, 7)),
+            ),
+            (
+                Box::new(MarbleTexture::new(3, 4.0, 7, Vec3(1.0, 1.0, 1.0))),
+                Box::new(MarbleTexture::new(3, 4.0, 7, Vec3(1.0, 1.0, 1.0))),
+            ),
+        ];
+        for (a, b) in &textures {
+            let value = a.value(0.0, 0.0, p);
+            assert_eq!(value.0, b.value(0.0, 0.0, p).0);
+            assert!((0.0..=1.0).contains(&value.0), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn image_is_filtered_bilinearly() {
+        let texture = gray_image(&[&[0.0, 1.0], &[2.0, 3.0]], WrapMode::Clamp);
+        // 像素中心取到像素本身，v = 0 是最下面一行
+        let cases = [
+            (0.25, 0.75, 0.0),
+            (0.75, 0.75, 1.0),
+            (0.25, 0.25, 2.0),
+            (0.75, 0.25, 3.0),
+            (0.5, 0.25, 2.5),
+            (0.25, 0.5, 1.0),
+            (0.5, 0.5, 1.5),
+            (0.375, 0.5, 1.25),
+        ];
+        for (u, v, expected) in cases {
+            let value = sample(&texture, u, v);
+            assert!(
+                (value - expected).abs() < 1e-5,
+                "({}, {}): {} expected {}",
+                u,
+                v,
+                value,
+                expected
+            );
+        }
+        assert_eq!(sample(&texture, f32::NAN, 0.5), 0.0);
+    }
+
+    #[test]
+    fn wrap_modes_map_out_of_range_texels() {
+        let row: &[f32] = &[0.0, 1.0, 2.0];
+        // 下标为 -2、-1、3、4 的像素中心分别取到的值
+        let cases = [
+            (WrapMode::Repeat, [1.0, 2.0, 0.0, 1.0]),
+            (WrapMode::Clamp, [0.0, 0.0, 2.0, 2.0]),
+            (WrapMode::Mirror, [1.0, 0.0, 2.0, 1.0]),
+        ];
+        for (wrap, expected) in cases {
+            let texture = gray_image(&[row], wrap);
+            for (i, expected) in [-2, -1, 3, 4].into_iter().zip(expected) {
+                let u = (i as f32 + 0.5) / 3.0;
+                let value = sample(&texture, u, 0.5);
+                assert!(
+                    (value - expected).abs() < 1e-4,
+                    "{:?} texel {}: {} expected {}",
+                    wrap,
+                    i,
+                    value,
+                    expected
+                );
+            }
+        }
+    }
+}
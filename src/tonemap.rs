@@ -0,0 +1,197 @@
+use crate::vec::Vec3;
+use lodepng::RGB;
+
+/// 把场景的线性辐射亮度压缩到 [0, 1] 的色调映射曲线
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// 不压缩，超过 1 的部分直接截断
+    None,
+    /// L / (1 + L)，作用在亮度上以保持色相
+    Reinhard,
+    /// 带白点的 Reinhard，亮度达到白点时映射为 1；不给白点时用整幅图的最大亮度
+    ExtendedReinhard { white_point: Option<f32> },
+    /// Krzysztof Narkowicz 对 ACES 胶片曲线的拟合
+    Aces,
+    /// John Hable 为 Uncharted 2 设计的胶片曲线
+    Uncharted2,
+}
+
+/// 输出 8 位图像时的曝光和色调映射设置
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    // 曝光补偿（EV），每增加 1 亮度翻倍
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            operator: ToneMapOperator::None,
+            exposure: 0.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// 把一组线性颜色映射成 sRGB 编码的 8 位颜色
+    pub fn map_to_rgb8(&self, pixels: &[Vec3]) -> Vec<RGB<u8>> {
+        let scale = libm::exp2f(self.exposure);
+        let operator = match self.operator {
+            ToneMapOperator::ExtendedReinhard { white_point: None } => {
+                let max_luminance = pixels
+                    .iter()
+                    .map(|p| (scale * *p).luminance())
+                    .filter(|l| l.is_finite())
+                    .fold(0.0, f32::max);
+                ToneMapOperator::ExtendedReinhard {
+                    white_point: Some(max_luminance),
+                }
+            }
+            operator => operator,
+        };
+        pixels
+            .iter()
+            .map(|p| {
+                let c = tone_map(operator, scale * *p);
+                RGB::new(encode_srgb(c.0), encode_srgb(c.1), encode_srgb(c.2))
+            })
+            .collect()
+    }
+}
+
+/// 对曝光后的线性颜色应用色调映射曲线，结果仍是线性的
+pub fn tone_map(operator: ToneMapOperator, color: Vec3) -> Vec3 {
+    let color = color.max(Vec3(0.0, 0.0, 0.0));
+    match operator {
+        ToneMapOperator::None => color,
+        ToneMapOperator::Reinhard => {
+            let l = color.luminance();
+            scale_luminance(color, l, l / (1.0 + l))
+        }
+        ToneMapOperator::ExtendedReinhard { white_point } => {
+            let l = color.luminance();
+            let white = white_point.unwrap_or(1.0).max(1e-4);
+            scale_luminance(color, l, l * (1.0 + l / (white * white)) / (1.0 + l))
+        }
+        ToneMapOperator::Aces => {
+            // 拟合曲线对应的输入需要先乘 0.6
+            let aces = |x: f32| {
+                let x = 0.6 * x;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            };
+            Vec3(aces(color.0), aces(color.1), aces(color.2))
+        }
+        ToneMapOperator::Uncharted2 => {
+            let curve = |x: f32| {
+                let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+            };
+            // 曝光偏移 2，线性白点 11.2
+            let white_scale = 1.0 / curve(11.2);
+            let map = |x: f32| curve(2.0 * x) * white_scale;
+            Vec3(map(color.0), map(color.1), map(color.2))
+        }
+    }
+}
+
+// 按亮度的比例缩放颜色
+fn scale_luminance(color: Vec3, from: f32, to: f32) -> Vec3 {
+    if from > 0.0 {
+        (to / from) * color
+    } else {
+        color
+    }
+}
+
+/// sRGB 的分段传递函数 (OETF)，输入输出都在 [0, 1]
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * libm::powf(x, 1.0 / 2.4) - 0.055
+    }
+}
+
+/// `srgb_oetf` 的逆变换，把 sRGB 编码的值还原成线性值
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        libm::powf((x + 0.055) / 1.055, 2.4)
+    }
+}
+
+fn encode_srgb(x: f32) -> u8 {
+    // NaN 经过 clamp 仍是 NaN，转换成 u8 时为 0
+    (255.0 * srgb_oetf(x.clamp(0.0, 1.0)) + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 5] = [
+        ToneMapOperator::None,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard {
+            white_point: Some(4.0),
+        },
+        ToneMapOperator::Aces,
+        ToneMapOperator::Uncharted2,
+    ];
+
+    #[test]
+    fn srgb_oetf_is_continuous_and_invertible() {
+        let knee = 0.0031308;
+        let below = srgb_oetf(knee);
+        let above = srgb_oetf(knee + 1e-7);
+        assert!((below - above).abs() < 1e-5, "{} {}", below, above);
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        for i in 0..=100 {
+            let x = i as f32 / 100.0;
+            assert!((srgb_to_linear(srgb_oetf(x)) - x).abs() < 1e-5, "{}", x);
+        }
+    }
+
+    #[test]
+    fn exposure_scales_by_powers_of_two() {
+        let pixels = [Vec3(0.02, 0.1, 0.3), Vec3(0.4, 0.2, 0.05)];
+        let doubled: Vec<Vec3> = pixels.iter().map(|&p| 4.0 * p).collect();
+        for operator in OPERATORS {
+            let exposed = ToneMapping {
+                operator,
+                exposure: 2.0,
+            };
+            let plain = ToneMapping {
+                operator,
+                exposure: 0.0,
+            };
+            assert_eq!(
+                exposed.map_to_rgb8(&pixels),
+                plain.map_to_rgb8(&doubled),
+                "{:?}",
+                operator
+            );
+        }
+    }
+
+    #[test]
+    fn operators_map_black_to_black_and_are_monotonic() {
+        for operator in OPERATORS {
+            let black = tone_map(operator, Vec3(0.0, 0.0, 0.0));
+            assert!(black.0.abs() < 1e-6, "{:?} maps 0 to {:?}", operator, black);
+            let mut previous = 0.0;
+            for i in 1..=200 {
+                let x = 0.05 * i as f32;
+                let y = tone_map(operator, Vec3(x, x, x)).0;
+                assert!(y >= previous, "{:?} decreases at {}", operator, x);
+                previous = y;
+            }
+        }
+        // 带白点的 Reinhard 在白点处为 1
+        let white = tone_map(OPERATORS[2], Vec3(4.0, 4.0, 4.0));
+        assert!((white.0 - 1.0).abs() < 1e-5, "{:?}", white);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::aabb::Aabb;
+use crate::hit::{Hit, Hittable};
+use crate::ray::Ray;
+use crate::utils::degrees_to_radians;
+use crate::vec::Vec3;
+use std::ops::Mul;
+use std::sync::Arc;
+
+/// 4x4 矩阵，按行存放，作用在列向量上
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4::from_rows([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn from_rows(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        Mat4::from_rows([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(factors: Vec3) -> Mat4 {
+        Mat4::from_rows([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// 绕任意轴旋转，角度为度，从轴的正方向看去为逆时针
+    pub fn rotation(axis: Vec3, degrees: f32) -> Mat4 {
+        let a = axis.to_unit_vector();
+        let theta = degrees_to_radians(degrees);
+        let (s, c) = (libm::sinf(theta), libm::cosf(theta));
+        let t = 1.0 - c;
+        Mat4::from_rows([
+            [
+                t * a.x() * a.x() + c,
+                t * a.x() * a.y() - s * a.z(),
+                t * a.x() * a.z() + s * a.y(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.y() + s * a.z(),
+                t * a.y() * a.y() + c,
+                t * a.y() * a.z() - s * a.x(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.z() - s * a.y(),
+                t * a.y() * a.z() + s * a.x(),
+                t * a.z() * a.z() + c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_x(degrees: f32) -> Mat4 {
+        Mat4::rotation(Vec3(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotation_y(degrees: f32) -> Mat4 {
+        Mat4::rotation(Vec3(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotation_z(degrees: f32) -> Mat4 {
+        Mat4::rotation(Vec3(0.0, 0.0, 1.0), degrees)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4::from_rows(m)
+    }
+
+    /// 用带部分主元的高斯-约当消元求逆，矩阵奇异时返回 None
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4::from_rows(inv))
+    }
+
+    /// 最后一行是 [0, 0, 0, 1]，点变换后 w 仍然是 1
+    pub fn is_affine(&self) -> bool {
+        self.m[3] == [0.0, 0.0, 0.0, 1.0]
+    }
+
+    /// 左上 3x3 部分的行列式
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// 变换点，包含平移，只用于仿射矩阵
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    /// 变换方向，不包含平移
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3(
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4::from_rows(m)
+    }
+}
+
+/// 把物体放到世界中的某个位置
+/// 光线先变换到物体空间求交，再把击中点和法线变换回世界空间。
+/// 物体用 `Arc` 共享，同一个网格可以放置很多次而不用复制。
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    // 物体空间到世界空间
+    matrix: Mat4,
+    // 世界空间到物体空间
+    inverse: Mat4,
+    // 逆矩阵的转置，用来变换法线
+    normal_matrix: Mat4,
+    // 左上 3x3 部分的行列式的绝对值
+    determinant: f32,
+    bbox: Aabb,
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hittable>) -> Transform {
+        let bbox = object.aabb();
+        Transform {
+            object,
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+            normal_matrix: Mat4::identity(),
+            determinant: 1.0,
+            bbox,
+        }
+    }
+
+    /// 使用任意仿射矩阵，矩阵不是仿射的或者不可逆时返回 None
+    pub fn with_matrix(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Transform> {
+        if !matrix.is_affine() {
+            return None;
+        }
+        let inverse = matrix.inverse()?;
+        Some(Transform::new(object).then(matrix, inverse))
+    }
+
+    pub fn translate(self, offset: Vec3) -> Transform {
+        self.then(Mat4::translation(offset), Mat4::translation(-offset))
+    }
+
+    pub fn rotate(self, axis: Vec3, degrees: f32) -> Transform {
+        let rotation = Mat4::rotation(axis, degrees);
+        self.then(rotation, rotation.transpose())
+    }
+
+    pub fn rotate_x(self, degrees: f32) -> Transform {
+        self.rotate(Vec3(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(self, degrees: f32) -> Transform {
+        self.rotate(Vec3(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(self, degrees: f32) -> Transform {
+        self.rotate(Vec3(0.0, 0.0, 1.0), degrees)
+    }
+
+    /// 各轴的缩放比例都不能为 0
+    pub fn scale(self, factors: Vec3) -> Transform {
+        assert!(factors.0 != 0.0 && factors.1 != 0.0 && factors.2 != 0.0);
+        let inverse = Vec3(1.0 / factors.0, 1.0 / factors.1, 1.0 / factors.2);
+        self.then(Mat4::scaling(factors), Mat4::scaling(inverse))
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    /// 方向不归一化，物体空间里的 t 和世界空间里的相同
+    fn object_ray(&self, ray: &Ray) -> Ray {
+        Ray::with_time(
+            self.inverse.transform_point(ray.origin),
+            self.inverse.transform_vector(ray.direction),
+            ray.time,
+        )
+    }
+
+    /// 在已有的变换之后再应用 `matrix`
+    fn then(mut self, matrix: Mat4, inverse: Mat4) -> Transform {
+        self.matrix = matrix * self.matrix;
+        self.inverse = self.inverse * inverse;
+        self.normal_matrix = self.inverse.transpose();
+        self.determinant = self.matrix.determinant3().abs();
+        self.bbox = transform_aabb(&self.matrix, &self.object.aabb());
+        self
+    }
+}
+
+/// 变换包围盒的八个顶点，再取它们的包围盒
+fn transform_aabb(matrix: &Mat4, bbox: &Aabb) -> Aabb {
+    if bbox.is_empty() {
+        return *bbox;
+    }
+    let mut result = Aabb::empty();
+    for i in 0..8 {
+        let corner = Vec3(
+            if i & 1 == 0 {
+                bbox.min.x()
+            } else {
+                bbox.max.x()
+            },
+            if i & 2 == 0 {
+                bbox.min.y()
+            } else {
+                bbox.max.y()
+            },
+            if i & 4 == 0 {
+                bbox.min.z()
+            } else {
+                bbox.max.z()
+            },
+        );
+        result = result.grow(matrix.transform_point(corner));
+    }
+    result
+}
+
+impl Hittable for Transform {
+    fn hit(&self, t_min: f32, t_max: f32, ray: &Ray) -> Option<Hit> {
+        let mut hit = self.object.hit(t_min, t_max, &self.object_ray(ray))?;
+        hit.p = self.matrix.transform_point(hit.p);
+        // 变换前后法线和光线方向的点积符号不变，front_face 不用重新计算
+        hit.normal = hit
+            .normal
+            .map(|n| self.normal_matrix.transform_vector(n).to_unit_vector());
+        Some(hit)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn transmittance(&self, t_min: f32, t_max: f32, ray: &Ray) -> f32 {
+        self.object
+            .transmittance(t_min, t_max, &self.object_ray(ray))
+    }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    // 方向 ω 经过线性变换 M 后变成 Mω / |Mω|，立体角被放大 |det M| / |Mω|³ 倍
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let ray = self.object_ray(&Ray::new(origin, direction));
+        let local = ray.direction.to_unit_vector();
+        let stretch = self.matrix.transform_vector(local).length();
+        self.object.pdf_value(ray.origin, local) * stretch * stretch * stretch / self.determinant
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let local = self.object.random(self.inverse.transform_point(origin));
+        self.matrix.transform_vector(local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::Sphere;
+    use crate::materials::Lambertian;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5))),
+        })
+    }
+
+    #[test]
+    fn inverse_undoes_composed_transforms() {
+        let matrix = Mat4::translation(Vec3(1.0, -2.0, 3.0))
+            * Mat4::rotation(Vec3(1.0, 2.0, 3.0), 40.0)
+            * Mat4::scaling(Vec3(2.0, 0.5, 3.0));
+        let inverse = matrix.inverse().unwrap();
+        for product in [matrix * inverse, inverse * matrix] {
+            for (i, row) in product.m.iter().enumerate() {
+                for (j, &value) in row.iter().enumerate() {
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((value - expected).abs() < 1e-5, "{:?}", product);
+                }
+            }
+        }
+        let p = Vec3(0.3, -0.7, 1.9);
+        assert_close(inverse.transform_point(matrix.transform_point(p)), p);
+        assert!(inverse.is_affine());
+        assert!(Mat4::scaling(Vec3(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn projective_matrices_are_rejected() {
+        let mut matrix = Mat4::identity();
+        matrix.m[3] = [0.0, 0.0, 1.0, 1.0];
+        assert!(!matrix.is_affine());
+        assert!(Transform::with_matrix(unit_sphere(), matrix).is_none());
+    }
+
+    #[test]
+    fn hits_round_trip_between_spaces() {
+        let object = Transform::new(unit_sphere())
+            .rotate_y(30.0)
+            .translate(Vec3(0.0, 0.0, -5.0));
+        let ray = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -2.0));
+        let hit = object.hit(0.001, f32::MAX, &ray).unwrap();
+        // 物体空间和世界空间里的 t 相同
+        assert!((hit.t - 2.0).abs() < 1e-4, "{}", hit.t);
+        assert_close(hit.p, ray.at(hit.t));
+        assert_close(hit.p, Vec3(0.0, 0.0, -4.0));
+        assert_close(hit.normal.unwrap(), Vec3(0.0, 0.0, 1.0));
+        let local = object.object_ray(&ray);
+        assert_close(object.matrix().transform_point(local.at(hit.t)), hit.p);
+        assert!(object
+            .hit(0.001, f32::MAX, &Ray::new(ray.origin, Vec3(1.0, 0.0, -1.0)))
+            .is_none());
+
+        let bbox = object.aabb();
+        assert!(bbox.min.z() <= -6.0 + 1e-4 && bbox.max.z() >= -4.0 - 1e-4);
+    }
+
+    #[test]
+    fn normals_use_the_inverse_transpose() {
+        // 缩放后的球是椭球 x²/4 + y² + z² = 1，(x, y, z) 处的法线方向是 (x/4, y, z)
+        let object = Transform::new(unit_sphere()).scale(Vec3(2.0, 1.0, 1.0));
+        let target = Vec3(2.0 * 0.6, 0.8, 0.0);
+        let origin = Vec3(3.0, 3.0, 0.0);
+        let hit = object
+            .hit(0.001, f32::MAX, &Ray::new(origin, target - origin))
+            .unwrap();
+        assert_close(hit.p, target);
+        let expected = Vec3(target.x() / 4.0, target.y(), target.z()).to_unit_vector();
+        assert_close(hit.normal.unwrap(), expected);
+    }
+}
@@ -1,5 +1,44 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
 use std::f32::consts::PI;
 
+thread_local! {
+    // 每个线程一个随机数生成器，设置种子后渲染结果可以复现
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
 pub fn degrees_to_radians(degrees: f32) -> f32 {
     degrees * PI / 180.0
 }
+
+/// 重新设置当前线程的随机数种子
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// 在 `f` 中用 `rng` 代替当前线程的随机数生成器，结束后（包括 panic 时）换回原来的
+/// `rng` 保留 `f` 用过以后的状态
+pub fn with_rng<T>(rng: &mut SmallRng, f: impl FnOnce() -> T) -> T {
+    struct Restore<'a>(&'a mut SmallRng);
+
+    impl Drop for Restore<'_> {
+        fn drop(&mut self) {
+            RNG.with(|current| std::mem::swap(&mut *current.borrow_mut(), self.0));
+        }
+    }
+
+    RNG.with(|current| std::mem::swap(&mut *current.borrow_mut(), rng));
+    let _restore = Restore(rng);
+    f()
+}
+
+/// [0, 1) 上均匀分布的随机数
+pub fn random_f32() -> f32 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// [min, max) 上均匀分布的随机数
+pub fn random_range(min: f32, max: f32) -> f32 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
+}
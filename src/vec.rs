@@ -1,9 +1,11 @@
+use crate::utils::{random_f32, random_range};
 use lodepng::RGB;
 use rand::distributions::{Distribution, Standard};
-use rand::{random, Rng};
+use rand::Rng;
+use serde::Deserialize;
 use std::ops::*;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub struct Vec3(pub f32, pub f32, pub f32);
 
 impl Vec3 {
@@ -37,7 +39,7 @@ impl Vec3 {
         self.length_squared().sqrt()
     }
 
-    pub fn to_u8(&self) -> [u8; 3] {
+    pub fn to_u8(self) -> [u8; 3] {
         fn u(f: f32) -> u8 {
             if f < 0.0 {
                 0
@@ -50,25 +52,34 @@ impl Vec3 {
         [u(self.0), u(self.1), u(self.2)]
     }
 
-    pub fn to_rgb(&self) -> RGB<u8> {
-        let rgb = &self.to_u8();
+    pub fn to_rgb(self) -> RGB<u8> {
+        let rgb = self.to_u8();
         RGB::new(rgb[0], rgb[1], rgb[2])
     }
 
-    pub fn to_rgb_sampled(&self, samples_per_pixel: usize) -> RGB<u8> {
-        let scale = 1.0 / (samples_per_pixel as f32);
-        // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-        let r = (scale * self.0 as f32).sqrt();
-        let r = (256.0 * f32::clamp(r, 0.0, 0.999)) as u8;
-        let g = (scale * self.1 as f32).sqrt();
-        let g = (256.0 * f32::clamp(g, 0.0, 0.999)) as u8;
-        let b = (scale * self.2 as f32).sqrt();
-        let b = (256.0 * f32::clamp(b, 0.0, 0.999)) as u8;
-        RGB::new(r, g, b)
+    pub fn to_unit_vector(self) -> Vec3 {
+        self / self.length()
     }
 
-    pub fn to_unit_vector(&self) -> Vec3 {
-        *self / self.length()
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3(
+            self.0.min(other.0),
+            self.1.min(other.1),
+            self.2.min(other.2),
+        )
+    }
+
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3(
+            self.0.max(other.0),
+            self.1.max(other.1),
+            self.2.max(other.2),
+        )
+    }
+
+    /// Rec. 709 相对亮度
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
     }
 
     /// Return true if the vector is close to zero in all dimensions.
@@ -126,6 +137,19 @@ impl Div<f32> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl Distribution<Vec3> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec3 {
         Vec3(rng.gen(), rng.gen(), rng.gen())
@@ -148,7 +172,7 @@ impl Vec3 {
     pub fn refract(v: &Vec3, n: &Vec3, etai_over_etat: f32) -> Vec3 {
         let cos_theta = f32::min((-(*v)).dot(*n), 1.0);
         let r_out_perp = etai_over_etat * (*v + cos_theta * (*n));
-        let r_out_parallel = libm::fabsf(1.0 - r_out_perp.length_squared()).sqrt() * -1.0 * *n;
+        let r_out_parallel = libm::fabsf(1.0 - r_out_perp.length_squared()).sqrt() * -*n;
         r_out_perp + r_out_parallel
     }
     pub fn other_refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
@@ -159,7 +183,7 @@ impl Vec3 {
     }
 
     pub fn random_in_unit_disk() -> Vec3 {
-        let gen_range = || -> f32 { rand::thread_rng().gen_range(-1.0..1.0) };
+        let gen_range = || -> f32 { random_range(-1.0, 1.0) };
         loop {
             let p = Vec3(gen_range(), gen_range(), 0.0);
             if p.length_squared() < 1.0 {
@@ -173,13 +197,13 @@ impl Vec3 {
     }
 
     pub fn random_color_in_range(min: f32, max: f32) -> Vec3 {
-        let gen_range = || -> f32 { rand::thread_rng().gen_range(min..max) };
+        let gen_range = || -> f32 { random_range(min, max) };
         Vec3(gen_range(), gen_range(), gen_range())
     }
 
     pub fn random_in_unit_sphere() -> Vec3 {
         loop {
-            let p = 2.0 * random::<Vec3>() - Vec3(1.0, 1.0, 1.0);
+            let p = 2.0 * Vec3(random_f32(), random_f32(), random_f32()) - Vec3(1.0, 1.0, 1.0);
             if p.length_squared() < 1.0 {
                 return p;
             }